@@ -6,29 +6,68 @@ var<private> VERTICES: array<vec2<f32>, 6> = array<vec2<f32>, 6>(
     vec2<f32>(1.0, 1.0),
 );
 
+// pixels between the finest lines at least, any closer and they blur into a gray mess
+const MIN_SPACING: f32 = 8.0;
+const MINOR_ALPHA: f32 = 0.06;
+const MAJOR_ALPHA: f32 = 0.15;
+const AXIS_ALPHA: f32 = 0.35;
+
+struct CameraUniform {
+    proj: mat4x4<f32>,
+    inverse_proj: mat4x4<f32>,
+}
+
+@group(0) @binding(0)
+var<uniform> camera: CameraUniform;
+
 struct VertexInput {
     @builtin(vertex_index) index: u32,
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
+    // where the corner of the screen is in the world, the camera can be rotated so this isn't
+    // just the screen position scaled
     @location(0) vert_position: vec2<f32>,
 }
 
 @vertex
 fn vs_main(
     in: VertexInput,
-    // instance: ParticleInstanceInput,
 ) -> VertexOutput {
     var out: VertexOutput;
 
-
-    out.vert_position = VERTICES[in.index];
-    out.clip_position = vec4<f32>(VERTICES[in.index].x, VERTICES[in.index].y, 0.0, 0.0);
+    let corner = VERTICES[in.index];
+    out.vert_position = (camera.inverse_proj * vec4<f32>(corner.x, corner.y, 0.0, 1.0)).xy;
+    out.clip_position = vec4<f32>(corner.x, corner.y, 0.0, 1.0);
     return out;
 }
 
+// 1 on a line `spacing` world units apart from the next, fading out over a pixel
+fn grid_line(position: vec2<f32>, spacing: f32, pixel: f32) -> f32 {
+    let distance = abs(fract(position / spacing + 0.5) - 0.5) * spacing / pixel;
+    return 1.0 - clamp(min(distance.x, distance.y), 0.0, 1.0);
+}
+
 @fragment
 fn fs_main(in: VertexOutput) -> @location(0) vec4<f32> {
-    return vec4<f32>(1.0, 1.0, 1.0, 1.0);
+    // world units per pixel
+    let pixel = max(length(dpdx(in.vert_position)), length(dpdy(in.vert_position)));
+
+    // powers of ten, every tenth line is a major one
+    let spacing = pow(10.0, ceil(log(pixel * MIN_SPACING) / log(10.0)));
+    // the minor lines fade out as they get close enough to be replaced by the major ones
+    let fade = clamp((spacing / pixel - MIN_SPACING) / (MIN_SPACING * 9.0), 0.0, 1.0);
+
+    let minor = grid_line(in.vert_position, spacing, pixel) * MINOR_ALPHA * fade;
+    let major = grid_line(in.vert_position, spacing * 10.0, pixel)
+        * mix(MINOR_ALPHA, MAJOR_ALPHA, fade);
+    let axis_distance = abs(in.vert_position) / pixel;
+    let axis = (1.0 - clamp(min(axis_distance.x, axis_distance.y), 0.0, 1.0)) * AXIS_ALPHA;
+
+    let alpha = max(max(minor, major), axis);
+    if alpha <= 0.0 {
+        discard;
+    }
+    return vec4<f32>(1.0, 1.0, 1.0, alpha);
 }
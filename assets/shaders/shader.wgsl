@@ -9,6 +9,7 @@ var<private> VERTICES: array<vec2<f32>, 3> = array<vec2<f32>, 3>(
 
 struct CameraUniform {
     proj: mat4x4<f32>,
+    inverse_proj: mat4x4<f32>,
 }
 
 struct VertexInput {
@@ -0,0 +1,192 @@
+use anyhow::Context;
+use serde::Serialize;
+use std::path::Path;
+use std::time::Instant;
+
+use crate::cli::{BatchSettings, Settings};
+use crate::engine::{camera::Camera2D, timer::FixedTimestep};
+use crate::offscreen::OffscreenRenderer;
+use crate::particle::diagnostics::Diagnostics;
+use crate::particle::simulation::NBodySimulation;
+use crate::particle::snapshot::{self, Format};
+use crate::particle::trajectory::{TrajectoryHeader, TrajectoryRecorder};
+use crate::scene::Scene;
+
+/// One line of diagnostics.csv
+#[derive(Serialize)]
+struct DiagnosticsRow {
+    step: u64,
+    time: f64,
+    kinetic_energy: f32,
+    potential_energy: f32,
+    total_energy: f32,
+    /// relative to the first row
+    energy_drift: f32,
+    momentum_x: f32,
+    momentum_y: f32,
+    angular_momentum: f32,
+    center_of_mass_x: f32,
+    center_of_mass_y: f32,
+}
+
+/// Runs the simulation for `batch.steps` fixed steps without a window, writing snapshots,
+/// diagnostics and rendered frames into `batch.output` as it goes
+pub fn run(settings: &Settings, batch: &BatchSettings) -> anyhow::Result<()> {
+    let (width, height) = batch.frame_size;
+    // same starting view as the window, unless a scene says otherwise
+    let mut camera = Camera2D::new(width as f32 / height as f32);
+    camera.zoom = 100.0;
+    let mut clock = FixedTimestep::default();
+    let mut simulation = initial_simulation(settings, &mut clock, &mut camera)?;
+    camera.update_projection_matrix();
+    if let Some(timestep) = batch.timestep {
+        clock.step = timestep;
+    }
+    if let Some(substeps) = batch.substeps {
+        clock.substeps = substeps;
+    }
+    // a scene could have been saved paused
+    simulation.is_running = true;
+    // measured here instead, only as often as they get written
+    simulation.diagnostics_interval = 0;
+
+    std::fs::create_dir_all(&batch.output)
+        .with_context(|| format!("couldn't create {}", batch.output.display()))?;
+
+    let mut recorder = match &settings.record {
+        Some(path) => {
+            let header = TrajectoryHeader {
+                every: settings.record_every,
+                timestep: clock.step,
+                substeps: clock.substeps,
+            };
+            let mut recorder = TrajectoryRecorder::create(path, header)?;
+            recorder.record(&simulation)?;
+            Some(recorder)
+        }
+        None => None,
+    };
+
+    let frames_path = batch.output.join("frames");
+    let renderer = if batch.frames_every > 0 {
+        std::fs::create_dir_all(&frames_path)
+            .with_context(|| format!("couldn't create {}", frames_path.display()))?;
+        let renderer = pollster::block_on(OffscreenRenderer::new(width, height, batch.software))?;
+        println!(
+            "rendering frames with {} ({:?})",
+            renderer.adapter.name, renderer.adapter.backend
+        );
+        Some(renderer)
+    } else {
+        None
+    };
+
+    let diagnostics_path = batch.output.join("diagnostics.csv");
+    let mut diagnostics = csv::Writer::from_path(&diagnostics_path)
+        .with_context(|| format!("couldn't create {}", diagnostics_path.display()))?;
+    let mut reference = None;
+
+    let started = Instant::now();
+    let mut snapshots = 0;
+    let mut frames = 0;
+    for step in 0..=batch.steps {
+        if step > 0 {
+            simulation.step(clock.step, clock.substeps);
+            if let Some(recorder) = &mut recorder {
+                recorder.record(&simulation)?;
+            }
+        }
+
+        if is_every(step, batch.diagnostics_every) || step == batch.steps {
+            let measured =
+                Diagnostics::measure(&simulation.particles, &simulation.forces, simulation.time);
+            let reference = reference.get_or_insert(measured);
+            diagnostics.serialize(DiagnosticsRow {
+                step,
+                time: measured.time,
+                kinetic_energy: measured.kinetic_energy,
+                potential_energy: measured.potential_energy,
+                total_energy: measured.total_energy(),
+                energy_drift: measured.energy_drift(reference),
+                momentum_x: measured.momentum.x,
+                momentum_y: measured.momentum.y,
+                angular_momentum: measured.angular_momentum,
+                center_of_mass_x: measured.center_of_mass.x,
+                center_of_mass_y: measured.center_of_mass.y,
+            })?;
+        }
+
+        if step == 0 || step == batch.steps || is_every(step, batch.snapshot_every) {
+            write_snapshot(&simulation, step, &batch.output, batch.format)?;
+            snapshots += 1;
+        }
+
+        if let Some(renderer) = &renderer {
+            if is_every(step, batch.frames_every) {
+                // numbered without gaps so ffmpeg's frame_%06d.png picks them all up
+                let path = frames_path.join(format!("frame_{frames:06}.png"));
+                renderer.save(&camera, &simulation.instances(), path)?;
+                frames += 1;
+            }
+        }
+    }
+
+    diagnostics.flush()?;
+    if let Some(recorder) = &mut recorder {
+        recorder.flush()?;
+    }
+
+    println!(
+        "{} steps of {}s in {:.2}s, {} particles left, wrote {} snapshots and {} frames to {}",
+        batch.steps,
+        clock.step,
+        started.elapsed().as_secs_f32(),
+        simulation.particles.len(),
+        snapshots,
+        frames,
+        batch.output.display()
+    );
+    Ok(())
+}
+
+/// Same priority as the window, a scene, then imported particles, then the scenario
+fn initial_simulation(
+    settings: &Settings,
+    clock: &mut FixedTimestep,
+    camera: &mut Camera2D,
+) -> anyhow::Result<NBodySimulation> {
+    if let Some(path) = &settings.scene {
+        return Ok(Scene::load(path)?.restore(clock, camera));
+    }
+    if let Some(path) = &settings.import {
+        let particles = snapshot::import_file(path, &settings.columns)?;
+        return Ok(NBodySimulation::from_particles(particles));
+    }
+
+    let seed = settings.seed.unwrap_or_else(rand::random);
+    // so a random run can be repeated
+    println!("{} with seed {seed}", settings.scenario.name());
+    Ok(crate::create_simulation(
+        settings.scenario,
+        settings.particle_count(),
+        seed,
+    ))
+}
+
+fn is_every(step: u64, every: u64) -> bool {
+    every != 0 && step.is_multiple_of(every)
+}
+
+fn write_snapshot(
+    simulation: &NBodySimulation,
+    step: u64,
+    output: &Path,
+    format: Format,
+) -> anyhow::Result<()> {
+    let extension = match format {
+        Format::Csv => "csv",
+        Format::JsonLines => "jsonl",
+    };
+    let path = output.join(format!("step_{step:08}.{extension}"));
+    snapshot::export_file(&simulation.particles, path)
+}
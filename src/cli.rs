@@ -0,0 +1,277 @@
+use anyhow::{anyhow, bail, Context};
+use std::path::PathBuf;
+
+use crate::particle::scenarios::Scenario;
+use crate::particle::snapshot::{ColumnMapping, Format};
+
+pub const USAGE: &str = "\
+usage: rpenguin [options]
+       rpenguin batch [options] [batch options]
+
+batch runs the simulation without opening a window, writes particle snapshots and diagnostics
+to the output directory and exits. it only needs a gpu for --frames-every, and even then the
+software renderer will do
+
+options:
+    --scenario <name>    initial conditions: random, grid, disk, plummer, binaries, galaxies
+    --count <n>          how many particles to generate, defaults depend on the scenario
+    --seed <n>           seed for the scenario, random if not given
+    --load <path>        start from a saved scene instead of a scenario
+    --import <path>      start from particles in a .csv or .jsonl file instead of a scenario
+    --columns <spec>     which columns --import reads, like x=pos_x,y=pos_y,mass=m
+    --record <path>      record a trajectory from the start
+    --record-every <n>   steps between recorded frames, 1 by default
+    --bindings <path>    key bindings to use if the file exists, ./bindings.json by default
+    -h, --help           print this and exit
+
+batch options:
+    --steps <n>          how many fixed steps to take, 1000 by default
+    --dt <seconds>       length of a step, 1/120 or whatever the loaded scene used by default
+    --substeps <n>       updates per step, 1 or whatever the loaded scene used by default
+    --output <dir>       where everything gets written, ./output by default
+    --format <format>    snapshot format, csv or jsonl
+    --snapshot-every <n> steps between snapshots, the first and last step always get one
+    --diagnostics-every <n>  steps between diagnostics rows, 10 by default
+    --frames-every <n>   render a png every n steps into <output>/frames, off by default
+    --size <w>x<h>       size of the rendered frames, 1280x720 by default
+    --software           render frames on the cpu even if there's a gpu";
+
+/// Everything that can be set from the command line
+#[derive(Debug, Clone, PartialEq)]
+pub struct Settings {
+    pub scenario: Scenario,
+    /// `None` uses `Scenario::default_count`
+    pub particle_count: Option<usize>,
+    /// `None` picks a random one
+    pub seed: Option<u64>,
+    /// Scene file to start from, takes priority over the scenario
+    pub scene: Option<PathBuf>,
+    /// Particle table to start from, takes priority over the scenario
+    pub import: Option<PathBuf>,
+    pub columns: ColumnMapping,
+    /// Trajectory file to record to
+    pub record: Option<PathBuf>,
+    pub record_every: u32,
+    /// `input::InputMap` file, the defaults get used if it doesn't exist
+    pub bindings: PathBuf,
+    /// `Some` when running headless with `rpenguin batch`
+    pub batch: Option<BatchSettings>,
+    pub help: bool,
+}
+
+/// The batch subcommand's options
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchSettings {
+    pub steps: u64,
+    /// `None` keeps the loaded scene's, or the default
+    pub timestep: Option<f32>,
+    pub substeps: Option<u32>,
+    pub output: PathBuf,
+    pub format: Format,
+    /// 0 only writes the first and last step
+    pub snapshot_every: u64,
+    /// 0 turns them off
+    pub diagnostics_every: u64,
+    /// 0 doesn't render any
+    pub frames_every: u64,
+    /// of the rendered frames, in pixels
+    pub frame_size: (u32, u32),
+    /// Render on the fallback adapter even if there's a gpu
+    pub software: bool,
+}
+
+impl Default for BatchSettings {
+    fn default() -> Self {
+        Self {
+            steps: 1000,
+            timestep: None,
+            substeps: None,
+            output: PathBuf::from("output"),
+            format: Format::Csv,
+            snapshot_every: 0,
+            diagnostics_every: 10,
+            frames_every: 0,
+            frame_size: (1280, 720),
+            software: false,
+        }
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            scenario: Scenario::Random,
+            particle_count: None,
+            seed: None,
+            scene: None,
+            import: None,
+            columns: ColumnMapping::default(),
+            record: None,
+            record_every: 1,
+            bindings: PathBuf::from("bindings.json"),
+            batch: None,
+            help: false,
+        }
+    }
+}
+
+impl Settings {
+    /// Parses the arguments, without the program name
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> anyhow::Result<Self> {
+        let mut settings = Self::default();
+        let mut args = args.into_iter().peekable();
+
+        if args.next_if(|arg| arg == "batch").is_some() {
+            settings.batch = Some(BatchSettings::default());
+        }
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| anyhow!("{arg} is missing its value"))
+            };
+
+            match arg.as_str() {
+                "--scenario" => {
+                    let name = value()?;
+                    settings.scenario = Scenario::from_name(&name)
+                        .ok_or_else(|| anyhow!("unknown scenario \"{name}\""))?;
+                }
+                "--count" => {
+                    settings.particle_count =
+                        Some(value()?.parse().context("--count takes a number")?);
+                }
+                "--seed" => {
+                    settings.seed = Some(value()?.parse().context("--seed takes a number")?);
+                }
+                "--load" => settings.scene = Some(value()?.into()),
+                "--import" => settings.import = Some(value()?.into()),
+                "--columns" => settings.columns = ColumnMapping::parse(&value()?)?,
+                "--record" => settings.record = Some(value()?.into()),
+                "--bindings" => settings.bindings = value()?.into(),
+                "--record-every" => {
+                    settings.record_every = value()?
+                        .parse()
+                        .ok()
+                        .filter(|&every| every > 0)
+                        .ok_or_else(|| anyhow!("--record-every takes a number above 0"))?;
+                }
+                "-h" | "--help" => settings.help = true,
+                "--software" => {
+                    settings
+                        .batch
+                        .as_mut()
+                        .ok_or_else(|| anyhow!("{arg} only works with batch"))?
+                        .software = true;
+                }
+
+                "--steps"
+                | "--dt"
+                | "--substeps"
+                | "--output"
+                | "--format"
+                | "--snapshot-every"
+                | "--diagnostics-every"
+                | "--frames-every"
+                | "--size" => {
+                    let batch = settings
+                        .batch
+                        .as_mut()
+                        .ok_or_else(|| anyhow!("{arg} only works with batch"))?;
+                    let value = value()?;
+                    match arg.as_str() {
+                        "--steps" => batch.steps = number(&arg, &value)?,
+                        "--dt" => {
+                            let timestep: f32 = number(&arg, &value)?;
+                            // written this way round so NaN fails too
+                            if !(timestep > 0.0 && timestep.is_finite()) {
+                                bail!("--dt has to be a finite number above 0");
+                            }
+                            batch.timestep = Some(timestep);
+                        }
+                        "--substeps" => {
+                            let substeps = number(&arg, &value)?;
+                            if substeps == 0 {
+                                bail!("--substeps has to be above 0");
+                            }
+                            batch.substeps = Some(substeps);
+                        }
+                        "--output" => batch.output = value.into(),
+                        "--format" => {
+                            batch.format = match value.as_str() {
+                                "csv" => Format::Csv,
+                                "jsonl" => Format::JsonLines,
+                                _ => bail!("unknown format \"{value}\", expected csv or jsonl"),
+                            };
+                        }
+                        "--snapshot-every" => batch.snapshot_every = number(&arg, &value)?,
+                        "--diagnostics-every" => batch.diagnostics_every = number(&arg, &value)?,
+                        "--frames-every" => batch.frames_every = number(&arg, &value)?,
+                        _ => {
+                            let (width, height) = value
+                                .split_once('x')
+                                .ok_or_else(|| anyhow!("--size takes <w>x<h>, got \"{value}\""))?;
+                            batch.frame_size = (number(&arg, width)?, number(&arg, height)?);
+                            if batch.frame_size.0 == 0 || batch.frame_size.1 == 0 {
+                                bail!("--size has to be at least 1x1");
+                            }
+                        }
+                    }
+                }
+                _ => bail!("unknown argument \"{arg}\""),
+            }
+        }
+
+        // --scenario can come after --count
+        if let (Some(count), Some(max)) = (settings.particle_count, settings.scenario.max_count()) {
+            if count > max {
+                bail!(
+                    "{} makes at most {max} particles, --count was {count}",
+                    settings.scenario.name()
+                );
+            }
+        }
+
+        Ok(settings)
+    }
+
+    pub fn particle_count(&self) -> usize {
+        self.particle_count
+            .unwrap_or_else(|| self.scenario.default_count())
+    }
+}
+
+fn number<T: std::str::FromStr>(arg: &str, value: &str) -> anyhow::Result<T> {
+    value
+        .parse()
+        .map_err(|_| anyhow!("{arg} takes a number, got \"{value}\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> anyhow::Result<Settings> {
+        Settings::from_args(args.split_whitespace().map(str::to_string))
+    }
+
+    #[test]
+    fn parses_the_timestep() {
+        let settings = parse("batch --dt 0.01").unwrap();
+        assert_eq!(settings.batch.unwrap().timestep, Some(0.01));
+    }
+
+    #[test]
+    fn rejects_bad_timesteps() {
+        for dt in ["NaN", "inf", "-inf", "0", "-0.01"] {
+            assert!(parse(&format!("batch --dt {dt}")).is_err(), "--dt {dt}");
+        }
+    }
+
+    #[test]
+    fn rejects_counts_past_the_scenarios_limit() {
+        assert!(parse("--count 32 --scenario binaries").is_err());
+        assert!(parse("--scenario binaries --count 16").is_ok());
+        assert!(parse("--scenario disk --count 100000").is_ok());
+    }
+}
@@ -1,13 +1,16 @@
 use glam::Vec4Swizzles;
 use winit::{
     dpi::{PhysicalPosition, PhysicalSize},
-    event::{DeviceEvent, ElementState, KeyEvent, MouseScrollDelta, WindowEvent},
-    keyboard::{KeyCode, PhysicalKey},
+    event::{DeviceEvent, MouseScrollDelta, WindowEvent},
 };
 
+/// half heights of the view panned per unit of raw mouse motion while the cursor is grabbed
 const SENSITIVITY_X: f64 = 0.003;
 const SENSITIVITY_Y: f64 = 0.003;
 
+/// how many pixels of trackpad scrolling count as one line of a mouse wheel
+const PIXELS_PER_LINE: f32 = 40.0;
+
 #[rustfmt::skip]
 pub const OPENGL_TO_WGPU_MATRIX: glam::Mat4 = glam::Mat4::from_cols_array(&[
     1.0, 0.0, 0.0, 0.0,
@@ -16,10 +19,20 @@ pub const OPENGL_TO_WGPU_MATRIX: glam::Mat4 = glam::Mat4::from_cols_array(&[
     0.0, 0.0, 0.0, 1.0,
 ]);
 
+/// What the shaders get, the grid needs the inverse to find out where its pixels are
+#[repr(C)]
+#[derive(Debug, Clone, Copy, bytemuck::Pod, bytemuck::Zeroable)]
+pub struct CameraUniform {
+    pub proj: glam::Mat4,
+    pub inverse_proj: glam::Mat4,
+}
+
 pub struct Camera2D {
     pub aspect: f32,
     pub position: glam::Vec3,
+    /// Around z, turning the camera counterclockwise turns the world clockwise on screen
     pub rotation: glam::Quat,
+    /// Half the height of the view in world units, bigger shows more
     pub zoom: f32,
 
     pub proj: glam::Mat4,
@@ -38,18 +51,72 @@ impl Camera2D {
     }
 
     pub fn update_projection_matrix(&mut self) {
-        let zoomsqred = self.zoom * self.zoom;
-        let left = (-zoomsqred + self.position.x) * self.aspect;
+        // `position` is the middle of the screen, the view is the camera undone
+        let half = self.half_extents();
+        let view = glam::Mat4::from_rotation_translation(self.rotation, self.position).inverse();
+
+        self.proj = glam::Mat4::orthographic_lh(-half.x, half.x, -half.y, half.y, 0.0, 1.0) * view;
+    }
+
+    pub fn uniform(&self) -> CameraUniform {
+        CameraUniform {
+            proj: self.proj,
+            inverse_proj: self.proj.inverse(),
+        }
+    }
 
-        let right = (zoomsqred + self.position.x) * self.aspect;
+    /// Counterclockwise angle in radians
+    pub fn angle(&self) -> f32 {
+        let x_axis = self.rotation * glam::Vec3::X;
+        x_axis.y.atan2(x_axis.x)
+    }
 
-        let top = zoomsqred + self.position.y;
-        let bottom = -zoomsqred + self.position.y;
+    pub fn set_angle(&mut self, angle: f32) {
+        self.rotation = glam::Quat::from_rotation_z(angle);
+    }
 
-        self.proj = glam::Mat4::orthographic_lh(left, right, bottom, top, 0.0, 1.0);
+    /// Turns the camera counterclockwise around the middle of the screen
+    pub fn rotate(&mut self, angle: f32) {
+        self.set_angle(self.angle() + angle);
+    }
+
+    /// Half the width and height of what's visible, in world units
+    pub fn half_extents(&self) -> glam::Vec2 {
+        glam::Vec2::new(self.zoom * self.aspect, self.zoom)
+    }
+
+    /// Zoom that shows the whole box between `min` and `max` with a bit of room around it
+    pub fn zoom_to_fit(&self, min: glam::Vec2, max: glam::Vec2) -> f32 {
+        // the box turned the way the screen is
+        let (sin, cos) = self.angle().sin_cos();
+        let (sin, cos) = (sin.abs(), cos.abs());
+        let half = (max - min) * 0.5 * 1.1;
+        let half = glam::Vec2::new(half.x * cos + half.y * sin, half.x * sin + half.y * cos);
+        half.y.max(half.x / self.aspect)
+    }
+
+    /// Where a pixel of the window is in the world. `screen` is in physical pixels from the top
+    /// left corner like winit gives them, `screen_size` is the size of the window. Goes through
+    /// `proj`, so it's only up to date after `update_projection_matrix`
+    pub fn screen_to_world(&self, screen: glam::Vec2, screen_size: glam::Vec2) -> glam::Vec2 {
+        // pixels go down, clip space goes up
+        let ndc = glam::Vec2::new(
+            screen.x / screen_size.x * 2.0 - 1.0,
+            1.0 - screen.y / screen_size.y * 2.0,
+        );
+        self.proj
+            .inverse()
+            .project_point3(ndc.extend(0.0))
+            .truncate()
+    }
 
-        // self.proj =
-        //     glam::Mat4::from_scale_rotation_translation(scale, self.rotation, self.position);
+    /// The other way around from `screen_to_world`
+    pub fn world_to_screen(&self, world: glam::Vec2, screen_size: glam::Vec2) -> glam::Vec2 {
+        let ndc = self.proj.project_point3(world.extend(0.0)).truncate();
+        glam::Vec2::new(
+            (ndc.x + 1.0) * 0.5 * screen_size.x,
+            (1.0 - ndc.y) * 0.5 * screen_size.y,
+        )
     }
 }
 
@@ -58,18 +125,50 @@ pub struct CameraController2D {
     /// units traveled per second
     pub speed: f32,
     pub markiplier: f32,
+    /// zoom gets multiplied by `exp(zoom_step)` per line scrolled, so every step feels the same
     pub zoom_step: f32,
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    /// radians per second while turning with the keys
+    pub rotation_speed: f32,
 
-    // inputs
-    // keyboard
+    // inputs, the keys and buttons behind them are up to `input::InputMap`
     pub up: bool,
     pub down: bool,
     pub left: bool,
     pub right: bool,
     pub mod_key: bool,
+    pub rotate_left: bool,
+    pub rotate_right: bool,
+    /// dragging turns the camera instead of moving it
+    pub rotating: bool,
 
     // mouse
     pub scroll_delta: f32,
+    /// where the cursor is in physical pixels, zooming keeps the world under it in place
+    pub cursor: glam::Vec2,
+    /// size of the window in physical pixels, has to be kept up to date for `cursor` to mean
+    /// anything
+    pub screen_size: glam::Vec2,
+    /// dragging moves the camera
+    pub panning: bool,
+    /// pixels dragged while `panning` since the last `process`
+    pan_delta: glam::Vec2,
+    /// the cursor is locked and raw mouse motion pans, see `dinput`
+    pub grabbed: bool,
+    /// raw mouse motion since the last `process` times the sensitivity, y goes down
+    grab_delta: glam::Vec2,
+    /// radians the cursor went around the middle of the screen while `rotating` since the last
+    /// `process`
+    rotate_delta: f32,
+
+    /// How quickly following and zooming to fit catch up, higher is snappier
+    pub smoothing: f32,
+    /// the camera ends up exactly on this once `follow_offset` dies down
+    follow_target: Option<glam::Vec2>,
+    follow_offset: glam::Vec2,
+    /// zoom being eased towards
+    target_zoom: Option<f32>,
 }
 
 impl Default for CameraController2D {
@@ -77,13 +176,30 @@ impl Default for CameraController2D {
         Self {
             speed: 1.0,
             zoom_step: 0.2,
+            min_zoom: 0.1,
+            max_zoom: 100_000.0,
+            rotation_speed: std::f32::consts::FRAC_PI_2,
             markiplier: 4.0,
             up: false,
             down: false,
             left: false,
             right: false,
             mod_key: false,
+            rotate_left: false,
+            rotate_right: false,
+            rotating: false,
             scroll_delta: 0.0,
+            cursor: glam::Vec2::ZERO,
+            screen_size: glam::Vec2::ONE,
+            panning: false,
+            pan_delta: glam::Vec2::ZERO,
+            grabbed: false,
+            grab_delta: glam::Vec2::ZERO,
+            rotate_delta: 0.0,
+            smoothing: 6.0,
+            follow_target: None,
+            follow_offset: glam::Vec2::ZERO,
+            target_zoom: None,
         }
     }
 }
@@ -105,47 +221,33 @@ impl CameraController2D {
                 self.scroll_delta -= ydelta;
                 true
             }
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state,
-                        physical_key: PhysicalKey::Code(keycode),
-                        ..
-                    },
+            // trackpads
+            WindowEvent::MouseWheel {
+                delta: MouseScrollDelta::PixelDelta(PhysicalPosition { y, .. }),
                 ..
             } => {
-                let is_pressed = state.is_pressed();
-
-                // TODO: REFACTOR INPUTS
-                // this should be short enough, only 6 cycles currently but this is suboptimal
-                match keycode {
-                    KeyCode::KeyW => {
-                        self.up = is_pressed;
-                        true
-                    }
-
-                    KeyCode::KeyD => {
-                        self.right = is_pressed;
-                        true
-                    }
-
-                    KeyCode::KeyA => {
-                        self.left = is_pressed;
-                        true
-                    }
-
-                    KeyCode::KeyS => {
-                        self.down = is_pressed;
-                        true
-                    }
-
-                    KeyCode::ShiftLeft => {
-                        self.mod_key = is_pressed;
-                        true
-                    }
-
-                    _ => false,
+                self.scroll_delta -= *y as f32 / PIXELS_PER_LINE;
+                true
+            }
+            WindowEvent::CursorMoved { position, .. } => {
+                let position = glam::Vec2::new(position.x as f32, position.y as f32);
+                if self.rotating {
+                    // y up like the world so counterclockwise stays positive
+                    let center = self.screen_size * 0.5;
+                    let from = (self.cursor - center) * glam::Vec2::new(1.0, -1.0);
+                    let to = (position - center) * glam::Vec2::new(1.0, -1.0);
+                    // atan2 is fine with the cursor right in the middle, unlike `angle_to`
+                    self.rotate_delta += from.perp_dot(to).atan2(from.dot(to));
+                } else if self.panning {
+                    self.pan_delta += position - self.cursor;
                 }
+                self.cursor = position;
+                false
+            }
+            WindowEvent::Resized(PhysicalSize { width, height }) => {
+                self.screen_size =
+                    glam::Vec2::new(*width as f32, *height as f32).max(glam::Vec2::ONE);
+                false
             }
             _ => false,
         }
@@ -176,20 +278,242 @@ impl CameraController2D {
             translation.y += 1.0;
         }
 
-        translation = translation.normalize_or_zero() * spelta;
+        // up is up on screen, wherever that is in the world
+        translation = camera.rotation * translation.normalize_or_zero() * spelta;
+        // moving by hand takes over from following
+        if translation != glam::Vec3::ZERO {
+            self.follow_target = None;
+        }
+
+        // grabbed, moving the mouse drags the world along like dragging would
+        if self.grab_delta != glam::Vec2::ZERO {
+            self.follow_target = None;
+            let dragged = glam::Vec3::new(self.grab_delta.x, -self.grab_delta.y, 0.0) * camera.zoom;
+            translation -= camera.rotation * dragged;
+            self.grab_delta = glam::Vec2::ZERO;
+        }
+
+        // Q turns the camera left, so the world goes right
+        let mut turn = 0.0;
+        if self.rotate_left {
+            turn += self.rotation_speed * delta;
+        }
+        if self.rotate_right {
+            turn -= self.rotation_speed * delta;
+        }
+        // dragging turns the world along with the cursor, the camera the other way
+        turn -= self.rotate_delta;
+        self.rotate_delta = 0.0;
+        if turn != 0.0 {
+            camera.rotate(turn);
+        }
+
+        // dragging with the mouse, the world sticks to the cursor
+        if self.pan_delta != glam::Vec2::ZERO {
+            self.follow_target = None;
+            camera.update_projection_matrix();
+            let dragged = camera.screen_to_world(self.pan_delta, self.screen_size)
+                - camera.screen_to_world(glam::Vec2::ZERO, self.screen_size);
+            translation -= dragged.extend(0.0);
+            self.pan_delta = glam::Vec2::ZERO;
+        }
+
+        // how far to close the gap to the targets this frame, the same no matter the frame rate
+        let ease = 1.0 - (-self.smoothing * delta).exp();
+
+        if let Some(target_zoom) = self.target_zoom {
+            // in log space so zooming in and out take as long
+            let target_zoom = target_zoom.clamp(self.min_zoom, self.max_zoom);
+            camera.zoom *= (target_zoom / camera.zoom).powf(ease);
+            if (camera.zoom / target_zoom - 1.0).abs() < 1e-3 {
+                camera.zoom = target_zoom;
+                self.target_zoom = None;
+            }
+        }
 
         // zooming in and out
         if self.scroll_delta != 0.0 {
-            // TODO: Clamp zoom
+            self.target_zoom = None;
             let multiplier = if self.mod_key { 4.0 } else { 1.0 };
-            camera.zoom = (camera.zoom + self.scroll_delta * self.zoom_step * multiplier).max(0.1);
+            let zoom = camera.zoom * (self.scroll_delta * self.zoom_step * multiplier).exp();
+            let zoom = zoom.clamp(self.min_zoom, self.max_zoom);
+            if self.follow_target.is_some() {
+                // the followed thing stays in the middle
+                camera.zoom = zoom;
+            } else {
+                self.zoom_around(camera, zoom, self.cursor);
+            }
             self.scroll_delta = 0.0;
         }
 
         camera.position += translation;
+
+        if let Some(target) = self.follow_target {
+            self.follow_offset *= 1.0 - ease;
+            camera.position = (target + self.follow_offset).extend(camera.position.z);
+        }
+    }
+
+    /// Sets the zoom while keeping whatever is under `anchor` (in pixels) where it is on screen
+    pub fn zoom_around(&self, camera: &mut Camera2D, zoom: f32, anchor: glam::Vec2) {
+        camera.update_projection_matrix();
+        let before = camera.screen_to_world(anchor, self.screen_size);
+        camera.zoom = zoom;
+        camera.update_projection_matrix();
+        let after = camera.screen_to_world(anchor, self.screen_size);
+        camera.position += (before - after).extend(0.0);
+    }
+
+    /// Keeps the camera centered on `target`, call it every frame with wherever that is now. The
+    /// camera starts from where it is and catches up smoothly
+    pub fn follow(&mut self, camera: &Camera2D, target: glam::Vec2) {
+        if self.follow_target.is_none() {
+            self.follow_offset = camera.position.truncate() - target;
+        }
+        self.follow_target = Some(target);
+    }
+
+    pub fn unfollow(&mut self) {
+        self.follow_target = None;
     }
 
-    pub fn dinput(&mut self, event: &DeviceEvent) {
-        todo!()
+    /// Eases the zoom to `zoom`, scrolling cancels it
+    pub fn zoom_to(&mut self, zoom: f32) {
+        self.target_zoom = Some(zoom);
+    }
+
+    /// A movement key is held or the mouse is dragging the camera around
+    pub fn is_moving(&self) -> bool {
+        self.up
+            || self.down
+            || self.left
+            || self.right
+            || self.pan_delta != glam::Vec2::ZERO
+            || self.grab_delta != glam::Vec2::ZERO
+    }
+
+    /// Raw mouse motion, only does anything while `grabbed`. Everything else is ignored
+    pub fn dinput(&mut self, event: &DeviceEvent) -> bool {
+        match event {
+            DeviceEvent::MouseMotion { delta: (x, y) } if self.grabbed => {
+                self.grab_delta +=
+                    glam::Vec2::new((x * SENSITIVITY_X) as f32, (y * SENSITIVITY_Y) as f32);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: glam::Vec2 = glam::Vec2::new(1280.0, 720.0);
+
+    /// Every combination of a few zooms, positions and angles. Positions are in half heights,
+    /// far away from the origin at a tiny zoom f32 runs out of digits long before the math is off
+    fn cameras() -> impl Iterator<Item = Camera2D> {
+        let zooms = [0.05, 1.0, 37.5, 2500.0];
+        let positions = [
+            glam::Vec2::ZERO,
+            glam::vec2(12.5, -3.0),
+            glam::vec2(-800.0, 450.0),
+        ];
+        let angles = [0.0, 0.6, -2.0, std::f32::consts::PI];
+        zooms.into_iter().flat_map(move |zoom| {
+            positions.into_iter().flat_map(move |position| {
+                angles.into_iter().map(move |angle| {
+                    let mut camera = Camera2D::new(SCREEN.x / SCREEN.y);
+                    camera.zoom = zoom;
+                    camera.position = (position * zoom).extend(0.0);
+                    camera.set_angle(angle);
+                    camera.update_projection_matrix();
+                    camera
+                })
+            })
+        })
+    }
+
+    #[test]
+    fn screen_round_trips() {
+        let pixels = [
+            glam::Vec2::ZERO,
+            SCREEN * 0.5,
+            SCREEN,
+            glam::vec2(100.0, 650.0),
+        ];
+        for camera in cameras() {
+            for pixel in pixels {
+                let back = camera.world_to_screen(camera.screen_to_world(pixel, SCREEN), SCREEN);
+                assert!(
+                    back.abs_diff_eq(pixel, 0.05),
+                    "{pixel} came back as {back} at zoom {} position {} angle {}",
+                    camera.zoom,
+                    camera.position,
+                    camera.angle()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn world_round_trips() {
+        for camera in cameras() {
+            let half = camera.half_extents();
+            for offset in [glam::Vec2::ZERO, half * 0.5, glam::vec2(-half.x, half.y)] {
+                let world = camera.position.truncate() + offset;
+                let back = camera.screen_to_world(camera.world_to_screen(world, SCREEN), SCREEN);
+                // f32 only has so many digits for big positions and zooms
+                let tolerance = 1e-4 * (camera.zoom + world.abs().max_element());
+                assert!(
+                    back.abs_diff_eq(world, tolerance),
+                    "{world} came back as {back} at zoom {} position {} angle {}",
+                    camera.zoom,
+                    camera.position,
+                    camera.angle()
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_turns_the_view() {
+        let mut camera = Camera2D::new(SCREEN.x / SCREEN.y);
+        camera.set_angle(std::f32::consts::FRAC_PI_2);
+        camera.update_projection_matrix();
+        // turned a quarter counterclockwise, the middle of the right edge looks at +y
+        let right = camera.screen_to_world(glam::vec2(SCREEN.x, SCREEN.y * 0.5), SCREEN);
+        assert!(
+            right.abs_diff_eq(glam::vec2(0.0, camera.aspect), 1e-5),
+            "{right}"
+        );
+        assert!(camera
+            .screen_to_world(SCREEN * 0.5, SCREEN)
+            .abs_diff_eq(glam::Vec2::ZERO, 1e-5));
+    }
+
+    #[test]
+    fn zoom_around_keeps_the_anchor() {
+        let controller = CameraController2D {
+            screen_size: SCREEN,
+            ..Default::default()
+        };
+        let anchor = glam::vec2(200.0, 500.0);
+        for mut camera in cameras() {
+            let before = camera.screen_to_world(anchor, SCREEN);
+            let zoom = camera.zoom * 0.5;
+            controller.zoom_around(&mut camera, zoom, anchor);
+            camera.update_projection_matrix();
+            let after = camera.screen_to_world(anchor, SCREEN);
+            let tolerance = 1e-4 * (camera.zoom + before.abs().max_element());
+            assert!(
+                after.abs_diff_eq(before, tolerance),
+                "{before} moved to {after} at zoom {} position {} angle {}",
+                camera.zoom,
+                camera.position,
+                camera.angle()
+            );
+        }
     }
 }
@@ -106,3 +106,42 @@ impl EguiRenderer {
         }
     }
 }
+
+/// Small line plot that stretches `values` to fit, with the label and latest value on top
+pub fn plot(ui: &mut egui::Ui, label: &str, values: &[f32]) {
+    ui.label(format!(
+        "{label}: {:.4e}",
+        values.last().copied().unwrap_or(0.0)
+    ));
+
+    let size = egui::vec2(ui.available_width(), 50.0);
+    let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+
+    if values.len() < 2 {
+        return;
+    }
+
+    let (min, max) = values.iter().fold((f32::MAX, f32::MIN), |(min, max), v| {
+        (min.min(*v), max.max(*v))
+    });
+    // a flat line would divide by 0
+    let range = if max > min { max - min } else { 1.0 };
+
+    let points = values
+        .iter()
+        .enumerate()
+        .map(|(i, v)| {
+            egui::pos2(
+                rect.left() + rect.width() * i as f32 / (values.len() - 1) as f32,
+                rect.bottom() - rect.height() * (v - min) / range,
+            )
+        })
+        .collect();
+
+    painter.add(egui::Shape::line(
+        points,
+        egui::Stroke::new(1.0, ui.visuals().text_color()),
+    ));
+}
@@ -26,8 +26,7 @@ impl GridRenderer {
             device,
             shader_collection,
             vec![],
-            // you'll need to add the buffer here
-            vec![],
+            vec![crate::camera_bind_group_layout(device)],
             surface_format,
             wgpu::PrimitiveTopology::TriangleList,
             None,
@@ -42,6 +41,8 @@ impl GridRenderer {
         &self,
         encoder: &mut wgpu::CommandEncoder,
         view: &wgpu::TextureView,
+        camera_bind_group: &wgpu::BindGroup,
+        clear_color: wgpu::Color,
         // screen_descriptor: &egui_wgpu::ScreenDescriptor,
     ) {
         {
@@ -51,7 +52,8 @@ impl GridRenderer {
                     view,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
+                        // the grid is translucent, drawn over last frame it would pile up
+                        load: wgpu::LoadOp::Clear(clear_color),
                         store: wgpu::StoreOp::Store,
                     },
                 })],
@@ -61,6 +63,7 @@ impl GridRenderer {
             });
 
             render_pass.set_pipeline(&self.render_pipeline);
+            render_pass.set_bind_group(0, camera_bind_group, &[]);
 
             render_pass.draw(0..6, 0..1);
         }
@@ -65,3 +65,59 @@ impl InstantTimer {
         self.start = Instant::now();
     }
 }
+
+/// Turns variable frame times into a whole number of fixed size simulation steps, the leftover
+/// time carries over to the next frame
+pub struct FixedTimestep {
+    /// seconds of simulation per step
+    pub step: f32,
+    /// every step gets split into this many smaller updates, more accurate but slower
+    pub substeps: u32,
+    /// if a frame takes so long that we would need more steps than this we drop the backlog
+    /// instead of falling further and further behind (spiral of death)
+    pub max_steps_per_frame: u32,
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    pub fn new(step: f32) -> Self {
+        Self {
+            step,
+            substeps: 1,
+            max_steps_per_frame: 8,
+            accumulator: 0.0,
+        }
+    }
+
+    /// Adds the frame time and returns how many steps should be run
+    pub fn advance(&mut self, delta: f32) -> u32 {
+        self.accumulator += delta;
+        let steps = (self.accumulator / self.step) as u32;
+
+        if steps > self.max_steps_per_frame {
+            self.accumulator = 0.0;
+            return self.max_steps_per_frame;
+        }
+
+        self.accumulator -= steps as f32 * self.step;
+        steps
+    }
+
+    /// How far we are between the last step and the next one, from 0 to 1, used to interpolate
+    /// what gets rendered
+    #[inline]
+    pub fn alpha(&self) -> f32 {
+        (self.accumulator / self.step).clamp(0.0, 1.0)
+    }
+
+    #[inline]
+    pub fn reset(&mut self) {
+        self.accumulator = 0.0;
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> Self {
+        Self::new(1.0 / 120.0)
+    }
+}
@@ -0,0 +1,569 @@
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use winit::event::{KeyEvent, MouseButton, WindowEvent};
+use winit::keyboard::{KeyCode, ModifiersState, PhysicalKey};
+
+/// Something a key or mouse button can be bound to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum Action {
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    /// Held to pan and zoom faster
+    Fast,
+    RotateLeft,
+    RotateRight,
+    ZoomIn,
+    ZoomOut,
+    /// Held while dragging the world around
+    DragPan,
+    /// Held while dragging to turn the camera around the middle of the screen
+    DragRotate,
+    /// Whatever the current tool does, selecting or spawning
+    UseTool,
+    /// Held so selecting adds to the selection instead of replacing it
+    AddToSelection,
+    ClearSelection,
+    DeleteSelection,
+    Reset,
+    Pause,
+    /// One step while paused
+    Step,
+    FollowSelection,
+    FollowCenterOfMass,
+    FitAll,
+    FitSelection,
+    /// Locks the cursor so moving the mouse pans, until it's pressed again
+    ToggleGrab,
+}
+
+impl Action {
+    pub const ALL: [Action; 23] = [
+        Action::PanUp,
+        Action::PanDown,
+        Action::PanLeft,
+        Action::PanRight,
+        Action::Fast,
+        Action::RotateLeft,
+        Action::RotateRight,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::DragPan,
+        Action::DragRotate,
+        Action::UseTool,
+        Action::AddToSelection,
+        Action::ClearSelection,
+        Action::DeleteSelection,
+        Action::Reset,
+        Action::Pause,
+        Action::Step,
+        Action::FollowSelection,
+        Action::FollowCenterOfMass,
+        Action::FitAll,
+        Action::FitSelection,
+        Action::ToggleGrab,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::PanUp => "Pan up",
+            Action::PanDown => "Pan down",
+            Action::PanLeft => "Pan left",
+            Action::PanRight => "Pan right",
+            Action::Fast => "Faster camera",
+            Action::RotateLeft => "Rotate left",
+            Action::RotateRight => "Rotate right",
+            Action::ZoomIn => "Zoom in",
+            Action::ZoomOut => "Zoom out",
+            Action::DragPan => "Drag to pan",
+            Action::DragRotate => "Drag to rotate",
+            Action::UseTool => "Select / spawn",
+            Action::AddToSelection => "Add to selection",
+            Action::ClearSelection => "Clear selection",
+            Action::DeleteSelection => "Delete selection",
+            Action::Reset => "Reset",
+            Action::Pause => "Pause",
+            Action::Step => "Step",
+            Action::FollowSelection => "Follow selection",
+            Action::FollowCenterOfMass => "Follow center of mass",
+            Action::FitAll => "Fit all",
+            Action::FitSelection => "Fit selection",
+            Action::ToggleGrab => "Grab cursor",
+        }
+    }
+}
+
+/// A key or a mouse button. Keys are physical, they're named after where they are on a qwerty
+/// keyboard whatever the layout
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum Input {
+    Key(KeyCode),
+    Mouse(MouseButton),
+}
+
+impl Input {
+    /// The input `event` presses or lets go of, and whether it's pressed. Held keys repeating
+    /// don't count
+    pub fn from_event(event: &WindowEvent) -> Option<(Input, bool)> {
+        match event {
+            WindowEvent::KeyboardInput {
+                event:
+                    KeyEvent {
+                        state,
+                        physical_key: PhysicalKey::Code(keycode),
+                        repeat: false,
+                        ..
+                    },
+                ..
+            } => Some((Input::Key(*keycode), state.is_pressed())),
+            WindowEvent::MouseInput { state, button, .. } => {
+                Some((Input::Mouse(*button), state.is_pressed()))
+            }
+            _ => None,
+        }
+    }
+
+    /// Shift, ctrl, alt or super, these only get bound on their own once they're let go
+    pub fn is_modifier(&self) -> bool {
+        matches!(
+            self,
+            Input::Key(
+                KeyCode::ShiftLeft
+                    | KeyCode::ShiftRight
+                    | KeyCode::ControlLeft
+                    | KeyCode::ControlRight
+                    | KeyCode::AltLeft
+                    | KeyCode::AltRight
+                    | KeyCode::SuperLeft
+                    | KeyCode::SuperRight
+            )
+        )
+    }
+}
+
+impl fmt::Display for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Key(keycode) => {
+                let name = format!("{keycode:?}");
+                let name = name
+                    .strip_prefix("Key")
+                    .or_else(|| name.strip_prefix("Digit"))
+                    .unwrap_or(&name);
+                f.write_str(name)
+            }
+            Input::Mouse(MouseButton::Other(button)) => write!(f, "Mouse {button}"),
+            Input::Mouse(button) => write!(f, "{button:?} mouse"),
+        }
+    }
+}
+
+/// Which modifiers have to be held along with a binding's input
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+}
+
+impl Modifiers {
+    pub const NONE: Modifiers = Modifiers {
+        ctrl: false,
+        shift: false,
+        alt: false,
+    };
+    pub const CTRL: Modifiers = Modifiers {
+        ctrl: true,
+        ..Modifiers::NONE
+    };
+
+    pub fn is_none(&self) -> bool {
+        *self == Modifiers::NONE
+    }
+
+    /// Everything `self` needs is held in `held`, more is fine
+    fn held_in(&self, held: Modifiers) -> bool {
+        (!self.ctrl || held.ctrl) && (!self.shift || held.shift) && (!self.alt || held.alt)
+    }
+
+    fn count(&self) -> usize {
+        self.ctrl as usize + self.shift as usize + self.alt as usize
+    }
+}
+
+impl From<ModifiersState> for Modifiers {
+    fn from(state: ModifiersState) -> Self {
+        Self {
+            ctrl: state.control_key(),
+            shift: state.shift_key(),
+            alt: state.alt_key(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Binding {
+    pub input: Input,
+    #[serde(default, skip_serializing_if = "Modifiers::is_none")]
+    pub modifiers: Modifiers,
+}
+
+impl Binding {
+    pub const fn key(keycode: KeyCode) -> Self {
+        Self {
+            input: Input::Key(keycode),
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    pub const fn mouse(button: MouseButton) -> Self {
+        Self {
+            input: Input::Mouse(button),
+            modifiers: Modifiers::NONE,
+        }
+    }
+
+    pub const fn with(self, modifiers: Modifiers) -> Self {
+        Self { modifiers, ..self }
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.modifiers.ctrl {
+            f.write_str("Ctrl+")?;
+        }
+        if self.modifiers.shift {
+            f.write_str("Shift+")?;
+        }
+        if self.modifiers.alt {
+            f.write_str("Alt+")?;
+        }
+        write!(f, "{}", self.input)
+    }
+}
+
+/// What every action is bound to, saved as json. An action can have any number of bindings and
+/// a binding can do more than one action
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct InputMap {
+    bindings: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        use Action::*;
+
+        let key = Binding::key;
+        let mouse = Binding::mouse;
+        let bindings = [
+            (PanUp, vec![key(KeyCode::KeyW)]),
+            (PanDown, vec![key(KeyCode::KeyS)]),
+            (PanLeft, vec![key(KeyCode::KeyA)]),
+            (PanRight, vec![key(KeyCode::KeyD)]),
+            (Fast, vec![key(KeyCode::ShiftLeft)]),
+            (RotateLeft, vec![key(KeyCode::KeyQ)]),
+            (RotateRight, vec![key(KeyCode::KeyE)]),
+            (ZoomIn, vec![key(KeyCode::Equal)]),
+            (ZoomOut, vec![key(KeyCode::Minus)]),
+            (
+                DragPan,
+                vec![mouse(MouseButton::Right), mouse(MouseButton::Middle)],
+            ),
+            (
+                DragRotate,
+                vec![
+                    mouse(MouseButton::Right).with(Modifiers::CTRL),
+                    mouse(MouseButton::Middle).with(Modifiers::CTRL),
+                ],
+            ),
+            (UseTool, vec![mouse(MouseButton::Left)]),
+            (AddToSelection, vec![key(KeyCode::ShiftLeft)]),
+            (ClearSelection, vec![key(KeyCode::Escape)]),
+            (DeleteSelection, vec![key(KeyCode::Delete)]),
+            (Reset, vec![key(KeyCode::KeyR)]),
+            (Pause, vec![key(KeyCode::Space)]),
+            (Step, vec![key(KeyCode::Period)]),
+            (FollowSelection, vec![key(KeyCode::KeyF)]),
+            (FollowCenterOfMass, vec![key(KeyCode::KeyC)]),
+            (FitAll, vec![key(KeyCode::KeyZ)]),
+            (FitSelection, vec![key(KeyCode::KeyX)]),
+            (ToggleGrab, vec![key(KeyCode::KeyG)]),
+        ];
+
+        Self {
+            bindings: bindings.into_iter().collect(),
+        }
+    }
+}
+
+impl InputMap {
+    pub fn bindings(&self, action: Action) -> &[Binding] {
+        self.bindings.get(&action).map_or(&[], Vec::as_slice)
+    }
+
+    /// Does nothing if it's already bound to exactly that
+    pub fn bind(&mut self, action: Action, binding: Binding) {
+        let bindings = self.bindings.entry(action).or_default();
+        if !bindings.contains(&binding) {
+            bindings.push(binding);
+        }
+    }
+
+    /// Removes the `index`th binding of `action`
+    pub fn unbind(&mut self, action: Action, index: usize) {
+        if let Some(bindings) = self.bindings.get_mut(&action) {
+            if index < bindings.len() {
+                bindings.remove(index);
+            }
+        }
+    }
+
+    /// Every binding of `action` for showing in the ui
+    pub fn describe(&self, action: Action) -> String {
+        match self.bindings(action) {
+            [] => "unbound".to_string(),
+            bindings => bindings
+                .iter()
+                .map(Binding::to_string)
+                .collect::<Vec<_>>()
+                .join(" or "),
+        }
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("couldn't write {}", path.display()))
+    }
+
+    /// Actions missing from the file keep their default bindings, so files from before an
+    /// action existed still load
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("couldn't read {}", path.display()))?;
+        let loaded: Self = serde_json::from_str(&json)
+            .with_context(|| format!("couldn't load bindings from {}", path.display()))?;
+
+        let mut map = Self::default();
+        map.bindings.extend(loaded.bindings);
+        Ok(map)
+    }
+}
+
+/// Keeps track of which actions are held down. An action stays held until the input that
+/// started it is let go, even if the modifiers or the bindings change in between
+#[derive(Debug, Default)]
+pub struct ActionState {
+    modifiers: Modifiers,
+    /// inputs held down and the actions each of them started
+    held: Vec<(Input, Vec<Action>)>,
+}
+
+impl ActionState {
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    pub fn set_modifiers(&mut self, state: ModifiersState) {
+        self.modifiers = state.into();
+    }
+
+    /// The actions `input` starts with the modifiers held right now. When some of them need
+    /// more modifiers than others only the most specific ones start, so ctrl and right drag
+    /// rotates instead of also panning
+    pub fn press(&mut self, map: &InputMap, input: Input) -> Vec<Action> {
+        if self.held.iter().any(|(held, _)| *held == input) {
+            return Vec::new();
+        }
+
+        let mut started = Vec::new();
+        let mut most_modifiers = 0;
+        for action in Action::ALL {
+            let matching = map
+                .bindings(action)
+                .iter()
+                .filter(|binding| {
+                    binding.input == input && binding.modifiers.held_in(self.modifiers)
+                })
+                .map(|binding| binding.modifiers.count())
+                .max();
+            match matching {
+                Some(count) if count > most_modifiers => {
+                    most_modifiers = count;
+                    started = vec![action];
+                }
+                Some(count) if count == most_modifiers => started.push(action),
+                _ => {}
+            }
+        }
+
+        self.held.push((input, started.clone()));
+        started.retain(|&action| self.holders(action) == 1);
+        started
+    }
+
+    /// The actions that stop being held now that `input` is let go
+    pub fn release(&mut self, input: Input) -> Vec<Action> {
+        let Some(index) = self.held.iter().position(|(held, _)| *held == input) else {
+            return Vec::new();
+        };
+        let (_, mut actions) = self.held.remove(index);
+        actions.retain(|&action| !self.is_held(action));
+        actions
+    }
+
+    /// Lets go of everything, for when the window loses focus and won't hear about it
+    pub fn release_all(&mut self) -> Vec<Action> {
+        let mut actions: Vec<Action> = self
+            .held
+            .drain(..)
+            .flat_map(|(_, actions)| actions)
+            .collect();
+        actions.sort_unstable();
+        actions.dedup();
+        actions
+    }
+
+    pub fn is_held(&self, action: Action) -> bool {
+        self.holders(action) > 0
+    }
+
+    /// how many held inputs are holding `action`
+    fn holders(&self, action: Action) -> usize {
+        self.held
+            .iter()
+            .filter(|(_, actions)| actions.contains(&action))
+            .count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PERIOD: Input = Input::Key(KeyCode::Period);
+    const RIGHT: Input = Input::Mouse(MouseButton::Right);
+
+    #[test]
+    fn looks_up_default_bindings() {
+        let map = InputMap::default();
+        let mut actions = ActionState::default();
+        assert_eq!(actions.press(&map, PERIOD), [Action::Step]);
+        assert_eq!(actions.press(&map, Input::Key(KeyCode::KeyM)), []);
+        // one key, two actions
+        assert_eq!(
+            actions.press(&map, Input::Key(KeyCode::ShiftLeft)),
+            [Action::Fast, Action::AddToSelection]
+        );
+    }
+
+    #[test]
+    fn most_modifiers_win() {
+        let map = InputMap::default();
+        let mut actions = ActionState::default();
+        assert_eq!(actions.press(&map, RIGHT), [Action::DragPan]);
+        actions.release(RIGHT);
+
+        actions.set_modifiers(ModifiersState::CONTROL);
+        assert_eq!(actions.press(&map, RIGHT), [Action::DragRotate]);
+        // letting go of ctrl first doesn't leave it stuck rotating
+        actions.set_modifiers(ModifiersState::empty());
+        assert_eq!(actions.release(RIGHT), [Action::DragRotate]);
+    }
+
+    #[test]
+    fn rebinding() {
+        let mut map = InputMap::default();
+        let key_m = Binding::key(KeyCode::KeyM);
+        map.bind(Action::Step, key_m);
+        map.bind(Action::Step, key_m);
+        assert_eq!(
+            map.bindings(Action::Step),
+            [Binding::key(KeyCode::Period), key_m]
+        );
+        assert_eq!(map.describe(Action::Step), "Period or M");
+
+        map.unbind(Action::Step, 0);
+        map.unbind(Action::Step, 5);
+        assert_eq!(map.bindings(Action::Step), [key_m]);
+        let mut actions = ActionState::default();
+        assert_eq!(actions.press(&map, PERIOD), []);
+        assert_eq!(
+            actions.press(&map, Input::Key(KeyCode::KeyM)),
+            [Action::Step]
+        );
+
+        map.unbind(Action::Step, 0);
+        assert_eq!(map.describe(Action::Step), "unbound");
+    }
+
+    #[test]
+    fn saved_bindings_merge_into_the_defaults() {
+        let path =
+            std::env::temp_dir().join(format!("rpenguin-bindings-{}.json", std::process::id()));
+        let mut map = InputMap::default();
+        map.unbind(Action::Pause, 0);
+        map.bind(
+            Action::Pause,
+            Binding::key(KeyCode::KeyP).with(Modifiers::CTRL),
+        );
+        map.save(&path).unwrap();
+
+        // a file from before most actions existed
+        let mut old: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        old.as_object_mut()
+            .unwrap()
+            .retain(|action, _| action == "Pause");
+        std::fs::write(&path, old.to_string()).unwrap();
+
+        let loaded = InputMap::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(loaded, map);
+    }
+
+    #[test]
+    fn pressed_held_released() {
+        let map = InputMap::default();
+        let mut actions = ActionState::default();
+        assert!(!actions.is_held(Action::Step));
+
+        // Step fires once per press, holding the key down doesn't fire it again
+        assert_eq!(actions.press(&map, PERIOD), [Action::Step]);
+        assert!(actions.is_held(Action::Step));
+        assert_eq!(actions.press(&map, PERIOD), []);
+        assert_eq!(actions.release(PERIOD), [Action::Step]);
+        assert!(!actions.is_held(Action::Step));
+        assert_eq!(actions.release(PERIOD), []);
+        assert_eq!(actions.press(&map, PERIOD), [Action::Step]);
+        actions.release(PERIOD);
+
+        // held by two inputs at once, only the first press and the last release count
+        let middle = Input::Mouse(MouseButton::Middle);
+        assert_eq!(actions.press(&map, RIGHT), [Action::DragPan]);
+        assert_eq!(actions.press(&map, middle), []);
+        assert_eq!(actions.release(RIGHT), []);
+        assert!(actions.is_held(Action::DragPan));
+        assert_eq!(actions.release(middle), [Action::DragPan]);
+    }
+
+    #[test]
+    fn release_all_lets_go_of_everything_once() {
+        let map = InputMap::default();
+        let mut actions = ActionState::default();
+        actions.press(&map, RIGHT);
+        actions.press(&map, Input::Mouse(MouseButton::Middle));
+        actions.press(&map, Input::Key(KeyCode::KeyW));
+        assert_eq!(actions.release_all(), [Action::PanUp, Action::DragPan]);
+        assert!(!actions.is_held(Action::DragPan));
+        assert_eq!(actions.release_all(), []);
+    }
+}
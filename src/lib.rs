@@ -5,12 +5,33 @@ mod engine;
 use engine::{egui_tools, prelude::*};
 
 mod particle;
+// so long recordings can be analysed without going through the ui
+pub use particle::{simulation::Particle, trajectory};
+
+pub mod batch;
+pub mod cli;
+mod input;
+pub mod offscreen;
+mod replay;
+mod scene;
+mod selection;
 
 use egui_wgpu::wgpu::util::DeviceExt;
 use egui_wgpu::{wgpu, ScreenDescriptor};
 use glam::Vec3Swizzles;
-use particle::simulation::{NBodySimulation, ParticleInstance};
+use input::{Action, ActionState, Binding, Input, InputMap};
+use particle::collision::CollisionMode;
+use particle::diagnostics::{ConservationBounds, Diagnostics};
+use particle::force::{ForceLaw, MIN_SOFTENING};
+use particle::scenarios::Scenario;
+use particle::simulation::{GravitySolver, NBodySimulation, ParticleInstance, DEFAULT_THETA};
+use particle::snapshot::{self, ColumnMapping};
+use particle::spawner::{Distribution, Spawner};
+use particle::trajectory::{TrajectoryHeader, TrajectoryRecorder};
 use render_pipeline::RenderPipelineBuilder;
+use replay::Replay;
+use scene::Scene;
+use selection::Selection;
 
 use winit::keyboard::KeyCode;
 use winit::{
@@ -19,6 +40,7 @@ use winit::{
     window::{Window, WindowBuilder},
 };
 
+use rand::{rngs::StdRng, SeedableRng};
 use std::time::Instant;
 
 /* const TOPOLOGIES: [wgpu::PrimitiveTopology; 3] = [
@@ -27,8 +49,15 @@ use std::time::Instant;
     wgpu::PrimitiveTopology::LineList,
 ]; */
 
-pub async fn run() {
+pub async fn run(settings: cli::Settings) -> anyhow::Result<()> {
     env_logger::init();
+    let scene = settings.scene.as_deref().map(Scene::load).transpose()?;
+    let imported = settings
+        .import
+        .as_deref()
+        .map(|path| snapshot::import_file(path, &settings.columns))
+        .transpose()?;
+
     let event_loop = EventLoop::new().unwrap();
     let window = WindowBuilder::new()
         .with_title("RPenguin")
@@ -37,60 +66,121 @@ pub async fn run() {
         .build(&event_loop)
         .unwrap();
 
-    // lock the cursor
-    /* window
-    .set_cursor_grab(winit::window::CursorGrabMode::Locked)
-    .unwrap_or_else(|_| eprintln!("couldn't grab cursor")); */
-    // window.set_cursor_visible(CURSOR_VISIBILITY);
-
     // create our meshes
 
-    let mut state = State::new(&window).await;
+    let mut state = State::new(&window, &settings, scene, imported).await;
+    if let Some(path) = &settings.record {
+        state.trajectory_path = path.display().to_string();
+        state.start_recording()?;
+    }
 
     let mut last_render_time = Instant::now();
-    event_loop
-        .run(move |event, control_flow| match event {
-            Event::WindowEvent {
-                ref event,
-                window_id,
-            } if window_id == state.window.id() => {
-                if !state.input(event) {
-                    match event {
-                        WindowEvent::CloseRequested => control_flow.exit(),
-                        WindowEvent::Resized(new_size) => state.resize(*new_size),
-
-                        WindowEvent::RedrawRequested => {
-                            let now = Instant::now();
-                            let delta = (now - last_render_time).as_secs_f32();
-                            state.update(delta);
-                            last_render_time = now;
-                            match state.render() {
-                                Ok(_) => (),
-                                // reconfiguring the surface recreates the swapchain
-                                Err(wgpu::SurfaceError::Lost) => state.reconfigure_surface(),
-                                Err(wgpu::SurfaceError::OutOfMemory) => control_flow.exit(),
-                                Err(e) => eprintln!("{e:?}"),
-                            }
+    event_loop.run(move |event, control_flow| match event {
+        Event::WindowEvent {
+            ref event,
+            window_id,
+        } if window_id == state.window.id() => {
+            if !state.input(event) {
+                match event {
+                    WindowEvent::CloseRequested => control_flow.exit(),
+                    WindowEvent::Resized(new_size) => state.resize(*new_size),
+
+                    WindowEvent::RedrawRequested => {
+                        let now = Instant::now();
+                        let delta = (now - last_render_time).as_secs_f32();
+                        state.update(delta);
+                        last_render_time = now;
+                        match state.render() {
+                            Ok(_) => (),
+                            // reconfiguring the surface recreates the swapchain
+                            Err(wgpu::SurfaceError::Lost) => state.reconfigure_surface(),
+                            Err(wgpu::SurfaceError::OutOfMemory) => control_flow.exit(),
+                            Err(e) => eprintln!("{e:?}"),
                         }
-
-                        _ => (),
                     }
+
+                    _ => (),
                 }
             }
-            Event::DeviceEvent { ref event, .. } => {
-                state.dinput(event);
-            }
+        }
+        Event::DeviceEvent { ref event, .. } => {
+            state.dinput(event);
+        }
 
-            Event::AboutToWait => {
-                // Redraw requested will only happen if we request it
-                state.window().request_redraw();
-            }
+        Event::AboutToWait => {
+            // Redraw requested will only happen if we request it
+            state.window().request_redraw();
+        }
 
-            _ => {}
-        })
-        .unwrap();
+        _ => {}
+    })?;
+
+    Ok(())
+}
+
+/// What a left click in the world does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Tool {
+    /// Click a particle or drag a box around some, shift adds to the selection
+    Select,
+    /// Like `Select` but draws around the particles instead of a box
+    Lasso,
+    /// Click for a particle, drag back and let go to launch it
+    Spawn,
+    Brush,
+    /// Click to paste the stamp
+    Stamp,
+}
+
+impl Tool {
+    const ALL: [Tool; 5] = [
+        Tool::Select,
+        Tool::Lasso,
+        Tool::Spawn,
+        Tool::Brush,
+        Tool::Stamp,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Tool::Select => "Select",
+            Tool::Lasso => "Lasso",
+            Tool::Spawn => "Spawn",
+            Tool::Brush => "Brush",
+            Tool::Stamp => "Stamp",
+        }
+    }
+}
+
+/// What the camera keeps in the middle of the screen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum CameraMode {
+    /// Only moves with the keys
+    Free,
+    /// Mass weighted center of the selected particles
+    Selection,
+    CenterOfMass,
 }
 
+impl CameraMode {
+    const ALL: [CameraMode; 3] = [
+        CameraMode::Free,
+        CameraMode::Selection,
+        CameraMode::CenterOfMass,
+    ];
+
+    fn name(&self) -> &'static str {
+        match self {
+            CameraMode::Free => "Free",
+            CameraMode::Selection => "Follow selection",
+            CameraMode::CenterOfMass => "Follow center of mass",
+        }
+    }
+}
+
+/// Pixels the mouse can move between press and release and still count as a click
+const CLICK_DISTANCE: f32 = 4.0;
+
 pub struct State<'a> {
     surface: wgpu::Surface<'a>,
     device: wgpu::Device,
@@ -102,18 +192,67 @@ pub struct State<'a> {
     grid_renderer: engine::rendering::grid_renderer::GridRenderer,
 
     nbody_simulation: NBodySimulation,
+    clock: timer::FixedTimestep,
+    /// what `create_simulation` got seeded with, R replays it
+    seed: u64,
+    scenario: Scenario,
+    particle_count: usize,
+    /// particles from the last import, replaying goes back to them instead of the scenario
+    imported: Option<Vec<Particle>>,
+    /// where the Info window saves and loads scenes
+    scene_path: String,
+    /// where the Info window imports and exports particles, csv or jsonl
+    particles_path: String,
+    /// `ColumnMapping::parse` spec used for imports
+    columns: String,
+    /// how the last save, load, import or export went
+    file_status: Option<String>,
+    /// where the Info window records trajectories to
+    trajectory_path: String,
+    /// a frame gets recorded every this many steps
+    record_every: u32,
+    recorder: Option<TrajectoryRecorder<std::fs::File>>,
+    /// while this is `Some` it gets shown instead of the simulation, which stays where it was
+    replay: Option<Replay>,
 
     // The window must be declared after the surface so
     // it gets dropped after it as the surface contains
     // unsafe references to the window's resources.
     window: &'a Window,
     mouse_position: glam::Vec2,
+    /// particles shown in the Inspector, cleared whenever the indices could have shifted
+    selection: Selection,
+    /// the box or lasso being dragged out, in world space. A box only uses the first point
+    selection_drag: Option<Vec<glam::Vec2>>,
+    /// what the Inspector recolors the selection with
+    group_color: glam::Vec3,
+    /// what the Inspector sets or adds to the velocity of the selection
+    group_velocity: glam::Vec2,
+    /// particles saved from a selection, centered on their center of mass
+    stamp: Option<Vec<Particle>>,
+    /// where stamps are saved and loaded, csv or jsonl
+    stamp_path: String,
+    tool: Tool,
+    spawner: Spawner,
+    /// where the slingshot is being pulled back from, in world space
+    drag_start: Option<glam::Vec2>,
+    /// the brush is held down
+    brushing: bool,
 
     camera: Camera2D,
     camera_buffer: wgpu::Buffer,
     camera_bind_group: wgpu::BindGroup,
 
     camera_controller: CameraController2D,
+    camera_mode: CameraMode,
+
+    input_map: InputMap,
+    actions: ActionState,
+    /// where the Controls window saves and loads bindings
+    bindings_path: String,
+    show_controls: bool,
+    /// waiting for a key or button to bind to this
+    rebinding: Option<Action>,
 
     pipeline_builder: render_pipeline::RenderPipelineBuilder<'a>,
     pipeline: wgpu::RenderPipeline,
@@ -123,7 +262,12 @@ pub struct State<'a> {
 }
 
 impl<'a> State<'a> {
-    async fn new(window: &'a Window) -> State<'a> {
+    async fn new(
+        window: &'a Window,
+        settings: &cli::Settings,
+        scene: Option<Scene>,
+        imported: Option<Vec<Particle>>,
+    ) -> State<'a> {
         let size = window.inner_size();
 
         let wgpu_instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
@@ -190,59 +334,48 @@ impl<'a> State<'a> {
 
         /* ----------------- N BODY SIMULATION ----------------- */
 
-        let nbody_simulation = create_simulation();
+        let seed = settings.seed.unwrap_or_else(rand::random);
+        let scenario = settings.scenario;
+        let particle_count = settings.particle_count();
 
         /* ----------------- CAMERA ----------------- */
 
         let mut camera = Camera2D::new(size.width as f32 / size.height as f32);
-        camera.zoom = 10.0;
-        /* {
-            let center = nbody_simulation.center();
-            camera.position = glam::Vec3::new(center.x, center.y, 0.0);
-        } */
+        camera.zoom = 100.0;
+        let mut clock = timer::FixedTimestep::default();
+        let nbody_simulation = match (&scene, &imported) {
+            (Some(scene), _) => scene.restore(&mut clock, &mut camera),
+            (None, Some(particles)) => NBodySimulation::from_particles(particles.clone()),
+            (None, None) => create_simulation(scenario, particle_count, seed),
+        };
         camera.update_projection_matrix();
         let camera_buffer = device.create_buffer_init(&wgpu::util::BufferInitDescriptor {
             label: Some("Camera projection matrix"),
-            contents: bytemuck::cast_slice(&[camera.proj]),
+            contents: bytemuck::cast_slice(&[camera.uniform()]),
             // you need to enable bytemuck feature in glam to do this ^^^^^^^^^
             usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
             // this is obviously a uniform ^^^  this is required to copy to it ^
         });
 
-        let camera_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("camera_bind_group_layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                }],
-            });
+        let camera_bind_group_layout = camera_bind_group_layout(&device);
 
-        let camera_bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
-            label: Some("camera_bind_group"),
-            layout: &camera_bind_group_layout,
-            entries: &[wgpu::BindGroupEntry {
-                binding: 0,
-                resource: camera_buffer.as_entire_binding(),
-            }],
-        });
+        let camera_bind_group =
+            camera_bind_group(&device, &camera_bind_group_layout, &camera_buffer);
 
-        let camera_controller = CameraController2D::new(6.0);
+        let mut camera_controller = CameraController2D::new(1.0);
+        camera_controller.screen_size = glam::Vec2::new(size.width as f32, size.height as f32);
 
-        /* ----------------- SHADERS ----------------- */
+        /* ----------------- INPUT ----------------- */
 
-        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
-            label: Some("shader"),
-            source: wgpu::ShaderSource::Wgsl(
-                include_str!(crate_path!("assets/shaders/shader.wgsl")).into(),
-            ),
-        });
+        // no file is fine, the defaults get used until the Controls window saves one
+        let (input_map, file_status) = if settings.bindings.exists() {
+            match InputMap::load(&settings.bindings) {
+                Ok(input_map) => (input_map, None),
+                Err(e) => (InputMap::default(), Some(format!("{e:#}"))),
+            }
+        } else {
+            (InputMap::default(), None)
+        };
 
         /* ----------------- VERTEX BUFFER ----------------- */
 
@@ -264,18 +397,8 @@ impl<'a> State<'a> {
 
         /* ----------------- RENDER PIPELINE ----------------- */
 
-        let pipeline_builder = RenderPipelineBuilder::new(
-            &device,
-            render_pipeline::ShaderCollection {
-                shaders: vec![shader],
-                ..Default::default()
-            },
-            vec![ParticleInstance::desc()],
-            vec![camera_bind_group_layout],
-            surface_format,
-            wgpu::PrimitiveTopology::TriangleList,
-            None,
-        );
+        let pipeline_builder =
+            particle_pipeline_builder(&device, camera_bind_group_layout, surface_format);
 
         let pipeline = pipeline_builder.build(&device);
 
@@ -299,6 +422,25 @@ impl<'a> State<'a> {
             grid_renderer,
 
             nbody_simulation,
+            clock,
+            seed,
+            scenario,
+            particle_count,
+            scene_path: settings.scene.as_ref().map_or_else(
+                || "scene.json".to_string(),
+                |path| path.display().to_string(),
+            ),
+            imported,
+            particles_path: settings.import.as_ref().map_or_else(
+                || "particles.csv".to_string(),
+                |path| path.display().to_string(),
+            ),
+            columns: String::new(),
+            file_status,
+            trajectory_path: "trajectory.bin".to_string(),
+            record_every: settings.record_every,
+            recorder: None,
+            replay: None,
 
             pipeline_builder,
             pipeline,
@@ -308,12 +450,29 @@ impl<'a> State<'a> {
             camera_bind_group,
 
             camera_controller,
+            camera_mode: CameraMode::Free,
+
+            input_map,
+            actions: ActionState::default(),
+            bindings_path: settings.bindings.display().to_string(),
+            show_controls: false,
+            rebinding: None,
 
             // The window must be declared after the surface so
             // it gets dropped after it as the surface contains
             // unsafe references to the window's resources.
             window,
             mouse_position: glam::Vec2::ZERO,
+            selection: Selection::default(),
+            selection_drag: None,
+            group_color: glam::Vec3::ONE,
+            group_velocity: glam::Vec2::ZERO,
+            stamp: None,
+            stamp_path: "stamp.csv".to_string(),
+            tool: Tool::Select,
+            spawner: Spawner::default(),
+            drag_start: None,
+            brushing: false,
 
             instances,
             instance_buffer,
@@ -337,63 +496,286 @@ impl<'a> State<'a> {
         }
     }
 
+    /// Raw device events come in whether the window is focused or not, only the camera cares
+    /// about them and only while the cursor is grabbed
     fn dinput(&mut self, event: &DeviceEvent) {
-        //
+        self.camera_controller.dinput(event);
+    }
+
+    /// Locks and hides the cursor so raw mouse motion pans the camera, or lets it go again.
+    /// Not every platform can lock it, confining it to the window is the next best thing
+    fn set_grab(&mut self, grab: bool) {
+        use winit::window::CursorGrabMode;
+
+        if !grab {
+            // can't fail in a way worth reporting, the cursor just stays as it is
+            let _ = self.window.set_cursor_grab(CursorGrabMode::None);
+            self.window.set_cursor_visible(true);
+            self.camera_controller.grabbed = false;
+            return;
+        }
+
+        let grabbed = self
+            .window
+            .set_cursor_grab(CursorGrabMode::Locked)
+            .or_else(|_| self.window.set_cursor_grab(CursorGrabMode::Confined));
+        match grabbed {
+            Ok(()) => {
+                self.window.set_cursor_visible(false);
+                self.camera_controller.grabbed = true;
+            }
+            Err(e) => self.file_status = Some(format!("couldn't grab the cursor: {e}")),
+        }
     }
 
     fn input(&mut self, event: &WindowEvent) -> bool {
         self.egui_renderer.handle_input(self.window, event);
 
-        self.camera_controller.input(event);
         match event {
-            WindowEvent::KeyboardInput {
-                event:
-                    KeyEvent {
-                        state,
-                        physical_key: winit::keyboard::PhysicalKey::Code(keycode),
-                        ..
-                    },
-                ..
-            } => {
-                if state.is_pressed() {
-                    match keycode {
-                        KeyCode::KeyR => {
-                            self.nbody_simulation = create_simulation();
-                        }
-                        KeyCode::Space => {
-                            self.nbody_simulation.is_running = !self.nbody_simulation.is_running;
-                        }
-                        _ => {}
-                    }
+            WindowEvent::ModifiersChanged(modifiers) => {
+                self.actions.set_modifiers(modifiers.state());
+            }
+            // anything let go while the window isn't focused never gets a release, and the
+            // cursor shouldn't stay stuck in a window that's in the background
+            WindowEvent::Focused(false) => {
+                for action in self.actions.release_all() {
+                    self.action(action, false);
                 }
+                self.set_grab(false);
             }
-            WindowEvent::CursorMoved { position, .. } => {
-                self.mouse_position.x = position.x as f32;
-                self.mouse_position.y = position.y as f32;
+            _ => {}
+        }
+
+        if let Some((input, pressed)) = Input::from_event(event) {
+            // typing in a text field or clicking on a window is for the ui. Letting go always
+            // counts, otherwise things could get stuck held
+            let context = self.egui_renderer.context();
+            let for_egui = match input {
+                Input::Key(_) => context.wants_keyboard_input(),
+                Input::Mouse(_) => context.is_pointer_over_area(),
+            };
+            // clicks on the Controls window itself (cancelling, saving, closing it) shouldn't end
+            // up as the new binding
+            let captured = match self.rebinding {
+                Some(action) if !(for_egui && matches!(input, Input::Mouse(_))) => {
+                    self.capture_binding(action, input, pressed)
+                }
+                _ => false,
+            };
+            if !pressed {
+                for action in self.actions.release(input) {
+                    self.action(action, false);
+                }
+            } else if !captured && !for_egui {
+                for action in self.actions.press(&self.input_map, input) {
+                    self.action(action, true);
+                }
+            }
+        }
+
+        // scrolling on a window shouldn't zoom the camera behind it
+        let scrolling_egui = matches!(event, WindowEvent::MouseWheel { .. })
+            && self.egui_renderer.context().is_pointer_over_area();
+        if !scrolling_egui {
+            self.camera_controller.input(event);
+        }
+
+        if let WindowEvent::CursorMoved { position, .. } = event {
+            self.mouse_position.x = position.x as f32;
+            self.mouse_position.y = position.y as f32;
+
+            if self.tool == Tool::Lasso {
+                let screen_size = self.screen_size();
+                let cursor = self.cursor_world_position();
+                if let Some(path) = &mut self.selection_drag {
+                    // no need for a point every pixel
+                    let last = self
+                        .camera
+                        .world_to_screen(path[path.len() - 1], screen_size);
+                    if last.distance(self.mouse_position) >= CLICK_DISTANCE {
+                        path.push(cursor);
+                    }
+                }
             }
-            _ => (),
         }
         false
     }
 
-    // TODO: delta is behaving oddly
+    /// Does whatever `action` does when its binding is pressed or let go
+    fn action(&mut self, action: Action, pressed: bool) {
+        let controller = &mut self.camera_controller;
+        match action {
+            Action::PanUp => controller.up = pressed,
+            Action::PanDown => controller.down = pressed,
+            Action::PanLeft => controller.left = pressed,
+            Action::PanRight => controller.right = pressed,
+            Action::Fast => controller.mod_key = pressed,
+            Action::RotateLeft => controller.rotate_left = pressed,
+            Action::RotateRight => controller.rotate_right = pressed,
+            Action::DragPan => controller.panning = pressed,
+            Action::DragRotate => controller.rotating = pressed,
+            Action::UseTool => self.use_tool(pressed),
+            // only ever checked with `is_held`
+            Action::AddToSelection => {}
+
+            // the rest happen once when pressed
+            _ if !pressed => {}
+            Action::ZoomIn => controller.scroll_delta -= 1.0,
+            Action::ZoomOut => controller.scroll_delta += 1.0,
+            Action::ClearSelection => self.selection.clear(),
+            Action::DeleteSelection if self.replay.is_none() => {
+                self.selection.delete(&mut self.nbody_simulation);
+            }
+            Action::DeleteSelection => {}
+            Action::Reset => self.reset_simulation(),
+            Action::Pause => match &mut self.replay {
+                Some(replay) => replay.toggle_playing(),
+                None => {
+                    self.nbody_simulation.is_running = !self.nbody_simulation.is_running;
+                }
+            },
+            Action::Step => self.step(),
+            Action::FollowSelection => self.toggle_camera_mode(CameraMode::Selection),
+            Action::FollowCenterOfMass => self.toggle_camera_mode(CameraMode::CenterOfMass),
+            Action::FitAll => self.zoom_to_fit(false),
+            Action::FitSelection => self.zoom_to_fit(true),
+            Action::ToggleGrab => self.set_grab(!self.camera_controller.grabbed),
+        }
+    }
+
+    /// Starts what the tool does when pressed, finishes it when let go. Replays can't be edited
+    fn use_tool(&mut self, pressed: bool) {
+        if !pressed {
+            if let Some(path) = self.selection_drag.take() {
+                self.finish_selection(&path);
+            }
+            if let Some(start) = self.drag_start.take() {
+                let particle = self.spawner.launch(start, self.cursor_world_position());
+                self.nbody_simulation.add_particles([particle]);
+            }
+            if self.brushing {
+                self.brushing = false;
+                self.spawner.stop_spraying();
+            }
+            return;
+        }
+        if self.replay.is_some() {
+            return;
+        }
+
+        let cursor = self.cursor_world_position();
+        match self.tool {
+            Tool::Select | Tool::Lasso => self.selection_drag = Some(vec![cursor]),
+            Tool::Spawn => self.drag_start = Some(cursor),
+            Tool::Brush => self.brushing = true,
+            Tool::Stamp => {
+                if let Some(stamp) = &self.stamp {
+                    let pasted = selection::paste(&mut self.nbody_simulation, stamp, cursor);
+                    self.selection.select(pasted, false);
+                }
+            }
+        }
+    }
+
+    /// A single step of the live simulation, only while it's paused
+    fn step(&mut self) {
+        if self.replay.is_some() || self.nbody_simulation.is_running {
+            return;
+        }
+        let count = self.nbody_simulation.particles.len();
+        self.nbody_simulation
+            .step(self.clock.step, self.clock.substeps);
+        self.record();
+        if self.nbody_simulation.particles.len() != count {
+            self.selection.clear();
+        }
+    }
+
+    /// Binds `input` to `action` for the Controls window, returns whether it got used up. Escape
+    /// gives up. Modifier keys only get bound on their own when they're let go without anything
+    /// else being pressed, until then they're held for whatever comes next
+    fn capture_binding(&mut self, action: Action, input: Input, pressed: bool) -> bool {
+        if input.is_modifier() {
+            if !pressed {
+                self.input_map.bind(
+                    action,
+                    Binding {
+                        input,
+                        modifiers: input::Modifiers::NONE,
+                    },
+                );
+                self.rebinding = None;
+            }
+            return true;
+        }
+        if !pressed {
+            return false;
+        }
+
+        if input != Input::Key(KeyCode::Escape) {
+            self.input_map.bind(
+                action,
+                Binding {
+                    input,
+                    modifiers: self.actions.modifiers(),
+                },
+            );
+        }
+        self.rebinding = None;
+        true
+    }
+
     fn update(&mut self, delta: f32) {
-        // let delta = self.last_render.elapsed().as_secs_f32();
-        // let delta = 1.0 / 30.0;
-        // println!("delta: {delta}");
         // printfps(delta);
 
-        self.camera.update_projection_matrix();
-        self.camera_controller.process(&mut self.camera, delta);
+        if let Some(replay) = &mut self.replay {
+            match replay.advance(delta) {
+                Ok(()) => self.instances = replay.simulation.instances(),
+                Err(e) => {
+                    self.file_status = Some(format!("replay stopped: {e:#}"));
+                    self.replay = None;
+                }
+            }
+        } else {
+            // the simulation runs at a fixed rate no matter the frame rate, whatever doesn't fit
+            // in a whole step gets interpolated
+            if self.nbody_simulation.is_running {
+                let count = self.nbody_simulation.particles.len();
+                let steps = self.clock.advance(delta);
+                for _ in 0..steps {
+                    self.nbody_simulation
+                        .step(self.clock.step, self.clock.substeps);
+                    self.record();
+                }
+                // merges shuffle the indices around
+                if self.nbody_simulation.particles.len() != count {
+                    self.selection.clear();
+                }
+            }
+            if self.brushing {
+                let particles = self.spawner.spray(self.cursor_world_position(), delta);
+                self.nbody_simulation.add_particles(particles);
+            }
+            // paused, the leftover time isn't going anywhere so show where the particles are
+            let alpha = if self.nbody_simulation.is_running {
+                self.clock.alpha()
+            } else {
+                1.0
+            };
+            self.instances = self.nbody_simulation.interpolated_instances(alpha);
+        }
 
-        self.nbody_simulation.update(delta);
-        self.instances = self.nbody_simulation.instances();
+        // after the particles moved so whatever is followed doesn't lag a frame behind
+        self.update_camera(delta);
+        if self.replay.is_none() {
+            self.highlight_selection();
+        }
         self.recreate_instance_buffer();
 
         self.queue.write_buffer(
             &self.camera_buffer,
             0,
-            bytemuck::cast_slice(&[self.camera.proj]),
+            bytemuck::cast_slice(&[self.camera.uniform()]),
         );
     }
 
@@ -409,36 +791,50 @@ impl<'a> State<'a> {
                 label: Some("Render Encoder"),
             });
 
-        self.grid_renderer.draw(&mut encoder, &view);
-
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
-                        store: wgpu::StoreOp::Store,
-                    },
-                })],
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: None,
-            });
-
-            render_pass.set_pipeline(&self.pipeline);
-            render_pass.set_bind_group(0, &self.camera_bind_group, &[]);
-            render_pass.set_vertex_buffer(0, self.instance_buffer.slice(..));
-
-            render_pass.draw(0..3, 0..self.instances.len() as u32);
-        }
+        draw_world(
+            &mut encoder,
+            &view,
+            self.clear_color,
+            &self.grid_renderer,
+            &self.pipeline,
+            &self.camera_bind_group,
+            &self.instance_buffer,
+            self.instances.len() as u32,
+        );
 
         let screen_descriptor = ScreenDescriptor {
             size_in_pixels: [self.config.width, self.config.height],
             pixels_per_point: self.window().scale_factor() as f32,
         };
 
+        let world_pos = self.cursor_world_position();
+        // egui works in points, the camera in physical pixels
+        let screen_size = self.screen_size();
+        let pixels_per_point = self.window().scale_factor() as f32;
+        let to_points = |world: glam::Vec2| {
+            let screen = self.camera.world_to_screen(world, screen_size) / pixels_per_point;
+            egui::pos2(screen.x, screen.y)
+        };
+        let cursor = to_points(world_pos);
+        let selection_polygon = self
+            .selection_drag
+            .as_ref()
+            .map(|path| self.selection_polygon(path));
+        let points_per_unit = to_points(glam::Vec2::X).x - to_points(glam::Vec2::ZERO).x;
+
+        // things the ui wants to do that need all of self, done once egui lets go of it
+        let mut reset = false;
+        let mut save_scene = false;
+        let mut load_scene = false;
+        let mut import_particles = false;
+        let mut export_particles = false;
+        let mut toggle_recording = false;
+        let mut toggle_replay = false;
+        let mut fit_all = false;
+        let mut fit_selection = false;
+        let mut camera_angle = None;
+        let mut degrees = self.camera.angle().to_degrees();
+
         self.egui_renderer.draw(
             &self.device,
             &self.queue,
@@ -447,6 +843,43 @@ impl<'a> State<'a> {
             &view,
             &screen_descriptor,
             |ctx| {
+                let painter = ctx.layer_painter(egui::LayerId::background());
+                let stroke = egui::Stroke::new(1.5, egui::Color32::from_gray(200));
+                if let Some(start) = self.drag_start {
+                    let start = to_points(start);
+                    painter.circle_stroke(start, self.spawner.radius * points_per_unit, stroke);
+                    painter.line_segment([start, cursor], stroke);
+                    // where it's going to go
+                    painter.arrow(
+                        start,
+                        start - cursor,
+                        egui::Stroke::new(1.5, egui::Color32::LIGHT_GREEN),
+                    );
+                }
+                if self.tool == Tool::Brush && self.replay.is_none() {
+                    painter.circle_stroke(
+                        cursor,
+                        self.spawner.brush_spread * points_per_unit,
+                        stroke,
+                    );
+                }
+                if let Some(polygon) = &selection_polygon {
+                    painter.add(egui::Shape::closed_line(
+                        polygon.iter().map(|&p| to_points(p)).collect(),
+                        stroke,
+                    ));
+                }
+                if let (Tool::Stamp, Some(stamp)) = (self.tool, &self.stamp) {
+                    // outlines get expensive, the first few are enough to line it up
+                    for p in stamp.iter().take(1000) {
+                        painter.circle_stroke(
+                            to_points(world_pos + p.position),
+                            p.radius * points_per_unit,
+                            stroke,
+                        );
+                    }
+                }
+
                 egui::Window::new("Info")
                     .resizable(true)
                     .vscroll(true)
@@ -460,30 +893,954 @@ impl<'a> State<'a> {
                             ));
                         });
 
-                        // TODO: figure out how to convert from screenspace to world space
-                        let world_pos = self.mouse_position;
-
                         ui.horizontal(|ui| {
                             ui.label("World Position:");
-                            // camera projection matrix translates world space to screen space, we
-                            // want to go from screen space to world space
-                            ui.label(format!("x:{:.2}, y:{:.2}", world_pos.x, -world_pos.y))
+                            ui.label(format!("x:{:.2}, y:{:.2}", world_pos.x, world_pos.y))
+                        });
+                        ui.separator();
+                        ui.horizontal(|ui| {
+                            // while replaying the frame being shown, not the live simulation
+                            let shown = match &mut self.replay {
+                                Some(replay) => &mut replay.simulation,
+                                None => &mut self.nbody_simulation,
+                            };
+                            let particle = shown.particle_at(world_pos);
+                            ui.label("Under cursor:");
+                            ui.label(match particle {
+                                Some(i) => format!("particle {i}"),
+                                None => "nothing".to_string(),
+                            });
                         });
+                        ui.toggle_value(&mut self.show_controls, "Controls");
                         ui.separator();
+
+                        ui.horizontal(|ui| {
+                            ui.label("Tool:");
+                            for tool in Tool::ALL {
+                                ui.selectable_value(&mut self.tool, tool, tool.name());
+                            }
+                        });
+                        if matches!(self.tool, Tool::Spawn | Tool::Brush) {
+                            let spawner = &mut self.spawner;
+                            ui.horizontal(|ui| {
+                                ui.label("Radius");
+                                ui.add(
+                                    egui::DragValue::new(&mut spawner.radius)
+                                        .speed(0.01)
+                                        .range(0.001..=f32::MAX),
+                                );
+                                ui.checkbox(&mut spawner.random_color, "Random color");
+                                if !spawner.random_color {
+                                    let mut color = spawner.color.to_array();
+                                    if ui.color_edit_button_rgb(&mut color).changed() {
+                                        spawner.color = color.into();
+                                    }
+                                }
+                            });
+                        }
+                        ui.separator();
+
+                        let camera_mode = self.camera_mode;
+                        ui.horizontal(|ui| {
+                            ui.label("Camera:");
+                            for mode in CameraMode::ALL {
+                                ui.selectable_value(&mut self.camera_mode, mode, mode.name());
+                            }
+                        });
+                        // start catching up from wherever the camera is now
+                        if self.camera_mode != camera_mode {
+                            self.camera_controller.unfollow();
+                        }
                         ui.horizontal(|ui| {
-                            let particle = self.nbody_simulation.particle_at(world_pos);
-                        })
+                            let map = &self.input_map;
+                            fit_all = ui
+                                .button(format!("Fit all ({})", map.describe(Action::FitAll)))
+                                .clicked();
+                            fit_selection = ui
+                                .add_enabled(
+                                    !self.selection.is_empty(),
+                                    egui::Button::new(format!(
+                                        "Fit selection ({})",
+                                        map.describe(Action::FitSelection)
+                                    )),
+                                )
+                                .clicked();
+                        });
+                        ui.add(
+                            egui::Slider::new(&mut self.camera_controller.smoothing, 1.0..=30.0)
+                                .text("Smoothing"),
+                        );
+                        let controller = &mut self.camera_controller;
+                        ui.horizontal(|ui| {
+                            ui.label("Zoom");
+                            ui.add(
+                                egui::DragValue::new(&mut controller.min_zoom)
+                                    .speed(0.01)
+                                    .range(0.001..=controller.max_zoom)
+                                    .prefix("min "),
+                            );
+                            ui.add(
+                                egui::DragValue::new(&mut controller.max_zoom)
+                                    .speed(10.0)
+                                    .range(controller.min_zoom..=f32::MAX)
+                                    .prefix("max "),
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Rotation");
+                            if ui
+                                .add(egui::DragValue::new(&mut degrees).speed(1.0).suffix("°"))
+                                .changed()
+                            {
+                                camera_angle = Some(degrees.to_radians());
+                            }
+                            if ui.button("Reset").clicked() {
+                                camera_angle = Some(0.0);
+                            }
+                        });
+                        let map = &self.input_map;
+                        ui.label(format!(
+                            "{} drag to pan, scroll zooms at the cursor",
+                            map.describe(Action::DragPan)
+                        ));
+                        ui.label(format!(
+                            "{} / {} or {} drag to rotate",
+                            map.describe(Action::RotateLeft),
+                            map.describe(Action::RotateRight),
+                            map.describe(Action::DragRotate)
+                        ));
+                        ui.label(format!(
+                            "{} grabs the cursor so moving the mouse pans",
+                            map.describe(Action::ToggleGrab)
+                        ));
+                        ui.label(format!(
+                            "{} follows the selection, {} the center of mass",
+                            map.describe(Action::FollowSelection),
+                            map.describe(Action::FollowCenterOfMass)
+                        ));
+                        ui.separator();
+
+                        match self.tool {
+                            Tool::Select | Tool::Lasso => {}
+                            Tool::Stamp => {
+                                ui.horizontal(|ui| {
+                                    ui.label("Stamp");
+                                    ui.text_edit_singleline(&mut self.stamp_path);
+                                    if ui.button("Load").clicked() {
+                                        self.file_status = Some(
+                                            match snapshot::import_file(
+                                                &self.stamp_path,
+                                                &ColumnMapping::default(),
+                                            ) {
+                                                Ok(stamp) => {
+                                                    let status = format!(
+                                                        "loaded a stamp of {} particles",
+                                                        stamp.len()
+                                                    );
+                                                    self.stamp = Some(selection::centered(stamp));
+                                                    status
+                                                }
+                                                Err(e) => format!("{e:#}"),
+                                            },
+                                        );
+                                    }
+                                });
+                                ui.label(match &self.stamp {
+                                    Some(stamp) => {
+                                        format!("{} particles, click to paste", stamp.len())
+                                    }
+                                    None => "Load one, or save a selection from the Inspector"
+                                        .to_string(),
+                                });
+                            }
+                            Tool::Spawn => {
+                                ui.add(
+                                    egui::Slider::new(
+                                        &mut self.spawner.launch_strength,
+                                        0.0..=10.0,
+                                    )
+                                    .text("Launch strength"),
+                                );
+                            }
+                            Tool::Brush => {
+                                let spawner = &mut self.spawner;
+                                ui.add(
+                                    egui::Slider::new(&mut spawner.brush_rate, 1.0..=1000.0)
+                                        .logarithmic(true)
+                                        .text("Particles per second"),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut spawner.brush_spread, 0.0..=100.0)
+                                        .text("Spread"),
+                                );
+                                egui::ComboBox::from_label("Distribution")
+                                    .selected_text(spawner.distribution.name())
+                                    .show_ui(ui, |ui| {
+                                        for distribution in Distribution::ALL {
+                                            ui.selectable_value(
+                                                &mut spawner.distribution,
+                                                distribution,
+                                                distribution.name(),
+                                            );
+                                        }
+                                    });
+                                ui.horizontal(|ui| {
+                                    ui.label("Velocity");
+                                    ui.add(
+                                        egui::DragValue::new(&mut spawner.brush_velocity.x)
+                                            .speed(0.1),
+                                    );
+                                    ui.add(
+                                        egui::DragValue::new(&mut spawner.brush_velocity.y)
+                                            .speed(0.1),
+                                    );
+                                });
+                            }
+                        }
+                        ui.separator();
+
+                        egui::ComboBox::from_label("Scenario")
+                            .selected_text(self.scenario.name())
+                            .show_ui(ui, |ui| {
+                                for scenario in Scenario::ALL {
+                                    if ui
+                                        .selectable_value(
+                                            &mut self.scenario,
+                                            scenario,
+                                            scenario.name(),
+                                        )
+                                        .clicked()
+                                    {
+                                        self.particle_count = scenario.default_count();
+                                        self.imported = None;
+                                        reset = true;
+                                    }
+                                }
+                            });
+                        ui.horizontal(|ui| {
+                            ui.label("Particles:");
+                            let max = self.scenario.max_count().unwrap_or(100_000);
+                            let count = ui
+                                .add(egui::DragValue::new(&mut self.particle_count).range(1..=max));
+                            if self.scenario == Scenario::Binaries {
+                                count.on_hover_text("rounded down to a power of 2");
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Seed:");
+                            ui.add(egui::DragValue::new(&mut self.seed));
+                            // so does the Reset action
+                            reset |= ui.button("Replay").clicked();
+                            if ui.button("New seed").clicked() {
+                                self.seed = rand::random();
+                                self.imported = None;
+                                reset = true;
+                            }
+                        });
+                        ui.separator();
+
+                        ui.horizontal(|ui| {
+                            ui.label("Scene:");
+                            ui.text_edit_singleline(&mut self.scene_path);
+                            save_scene = ui.button("Save").clicked();
+                            load_scene = ui.button("Load").clicked();
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Particles:");
+                            ui.text_edit_singleline(&mut self.particles_path);
+                            export_particles = ui.button("Export").clicked();
+                            import_particles = ui.button("Import").clicked();
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Columns:");
+                            ui.add(
+                                egui::TextEdit::singleline(&mut self.columns)
+                                    .hint_text("x=pos_x,y=pos_y,mass=m"),
+                            );
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Trajectory:");
+                            ui.add_enabled(
+                                self.recorder.is_none(),
+                                egui::TextEdit::singleline(&mut self.trajectory_path),
+                            );
+                            let label = match self.recorder {
+                                Some(_) => "Stop",
+                                None => "Record",
+                            };
+                            toggle_recording = ui.button(label).clicked();
+                            let label = match self.replay {
+                                Some(_) => "Close replay",
+                                None => "Replay",
+                            };
+                            toggle_replay = ui.button(label).clicked();
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Every");
+                            ui.add_enabled(
+                                self.recorder.is_none(),
+                                egui::DragValue::new(&mut self.record_every).range(1..=10_000),
+                            );
+                            ui.label("steps");
+                            if let Some(recorder) = &self.recorder {
+                                ui.label(format!("{} frames", recorder.frames()));
+                            }
+                        });
+                        if let Some(replay) = &mut self.replay {
+                            ui.horizontal(|ui| {
+                                // space does the same thing
+                                let label = if replay.is_playing { "Pause" } else { "Play" };
+                                if ui.button(label).clicked() {
+                                    replay.toggle_playing();
+                                }
+                                ui.checkbox(&mut replay.is_reversed, "Reverse");
+                                ui.add(
+                                    egui::Slider::new(&mut replay.speed, 0.05..=20.0)
+                                        .logarithmic(true)
+                                        .text("speed"),
+                                );
+                            });
+                            let (start, end) = replay.duration();
+                            let timeline = ui
+                                .add(egui::Slider::new(&mut replay.time, start..=end).suffix(" s"));
+                            // scrubbing pauses, like any video player
+                            if timeline.drag_started() {
+                                replay.is_playing = false;
+                            }
+                            ui.label(format!(
+                                "frame {} of {}",
+                                replay.frame() + 1,
+                                replay.frames()
+                            ));
+                        }
+                        if let Some(status) = &self.file_status {
+                            ui.label(status);
+                        }
+                        ui.separator();
+
+                        let forces = &mut self.nbody_simulation.forces;
+                        egui::ComboBox::from_label("Force law")
+                            .selected_text(forces.law.name())
+                            .show_ui(ui, |ui| {
+                                for law in ForceLaw::ALL {
+                                    let selected = forces.law.name() == law.name();
+                                    if ui.selectable_label(selected, law.name()).clicked()
+                                        && !selected
+                                    {
+                                        forces.law = law;
+                                    }
+                                }
+                            });
+                        match &mut forces.law {
+                            ForceLaw::InverseSquare | ForceLaw::InverseLinear => {
+                                ui.add(
+                                    egui::DragValue::new(&mut forces.gravitational_constant)
+                                        .prefix("G: "),
+                                );
+                                ui.add(
+                                    egui::Slider::new(&mut forces.softening, MIN_SOFTENING..=5.0)
+                                        .text("softening"),
+                                );
+                            }
+                            ForceLaw::LennardJones { depth, sigma } => {
+                                ui.add(egui::DragValue::new(depth).prefix("depth: "));
+                                ui.add(egui::DragValue::new(sigma).prefix("sigma: "));
+                            }
+                            ForceLaw::Spring {
+                                stiffness,
+                                rest_length,
+                            } => {
+                                ui.add(egui::DragValue::new(stiffness).prefix("stiffness: "));
+                                ui.add(egui::DragValue::new(rest_length).prefix("rest length: "));
+                            }
+                        }
+
+                        let solver = &mut self.nbody_simulation.solver;
+                        ui.horizontal(|ui| {
+                            ui.label("Solver:");
+                            let is_barnes_hut = matches!(solver, GravitySolver::BarnesHut { .. });
+                            if ui.radio(!is_barnes_hut, "Direct sum").clicked() {
+                                *solver = GravitySolver::DirectSum;
+                            }
+                            if ui.radio(is_barnes_hut, "Barnes-Hut").clicked() && !is_barnes_hut {
+                                *solver = GravitySolver::BarnesHut {
+                                    theta: DEFAULT_THETA,
+                                };
+                            }
+                        });
+                        if let GravitySolver::BarnesHut { theta } = solver {
+                            ui.add(egui::Slider::new(theta, 0.0..=1.5).text("theta"));
+                        }
+
+                        let collision_mode = &mut self.nbody_simulation.collision_mode;
+                        egui::ComboBox::from_label("Collisions")
+                            .selected_text(collision_mode.name())
+                            .show_ui(ui, |ui| {
+                                for mode in CollisionMode::ALL {
+                                    let selected = collision_mode.name() == mode.name();
+                                    if ui.selectable_label(selected, mode.name()).clicked()
+                                        && !selected
+                                    {
+                                        *collision_mode = mode;
+                                    }
+                                }
+                            });
+                        if let CollisionMode::Elastic { restitution } = collision_mode {
+                            ui.add(egui::Slider::new(restitution, 0.0..=1.0).text("restitution"));
+                        }
+                        let cell_size = &mut self.nbody_simulation.cell_size;
+                        ui.horizontal(|ui| {
+                            let mut auto = cell_size.is_none();
+                            if ui.checkbox(&mut auto, "Auto cell size").changed() {
+                                *cell_size = if auto { None } else { Some(2.0) };
+                            }
+                            if let Some(size) = cell_size {
+                                ui.add(egui::DragValue::new(size).speed(0.1).range(0.1..=100.0));
+                            }
+                        });
+
+                        let clock = &mut self.clock;
+                        ui.horizontal(|ui| {
+                            let mut rate = 1.0 / clock.step;
+                            ui.label("Steps per second:");
+                            if ui
+                                .add(egui::DragValue::new(&mut rate).range(1.0..=2000.0))
+                                .changed()
+                            {
+                                clock.step = 1.0 / rate;
+                            }
+                        });
+                        ui.add(egui::Slider::new(&mut clock.substeps, 1..=32).text("substeps"));
+                        ui.add(
+                            egui::Slider::new(&mut clock.max_steps_per_frame, 1..=64)
+                                .text("max steps per frame"),
+                        );
+                        ui.label(format!(
+                            "t = {:.2}s, step {}",
+                            self.nbody_simulation.time, self.nbody_simulation.steps
+                        ));
+
+                        let integrator = &mut self.nbody_simulation.integrator;
+                        egui::ComboBox::from_label("Integrator")
+                            .selected_text(integrator.name())
+                            .show_ui(ui, |ui| {
+                                for name in particle::integrator::INTEGRATORS {
+                                    let selected = integrator.name() == name;
+                                    if ui.selectable_label(selected, name).clicked() && !selected {
+                                        *integrator = particle::integrator::by_name(name).unwrap();
+                                    }
+                                }
+                            });
+                        ui.separator();
+
+                        let simulation = &mut self.nbody_simulation;
+                        ui.collapsing("Diagnostics", |ui| {
+                            ui.add(
+                                egui::Slider::new(&mut simulation.diagnostics_interval, 0..=100)
+                                    .text("measure every n steps"),
+                            );
+
+                            let history = &simulation.diagnostics;
+                            let (Some(first), Some(last)) = (history.front(), history.back())
+                            else {
+                                ui.label("Nothing measured yet");
+                                return;
+                            };
+                            let series = |f: fn(&Diagnostics) -> f32| -> Vec<f32> {
+                                history.iter().map(f).collect()
+                            };
+
+                            egui_tools::plot(ui, "Kinetic energy", &series(|d| d.kinetic_energy));
+                            egui_tools::plot(
+                                ui,
+                                "Potential energy",
+                                &series(|d| d.potential_energy),
+                            );
+                            egui_tools::plot(ui, "Total energy", &series(|d| d.total_energy()));
+                            egui_tools::plot(ui, "Momentum x", &series(|d| d.momentum.x));
+                            egui_tools::plot(ui, "Momentum y", &series(|d| d.momentum.y));
+                            egui_tools::plot(
+                                ui,
+                                "Angular momentum",
+                                &series(|d| d.angular_momentum),
+                            );
+                            ui.label(format!(
+                                "Center of mass: {:.2}, {:.2}",
+                                last.center_of_mass.x, last.center_of_mass.y
+                            ));
+
+                            // compared to the oldest sample that is still in the plots
+                            let failed = last.check(first, &ConservationBounds::default());
+                            if failed.is_empty() {
+                                ui.label("Within conservation bounds");
+                            }
+                            for message in failed {
+                                ui.colored_label(egui::Color32::RED, message);
+                            }
+                        });
                     });
+
+                egui::Window::new("Controls")
+                    .open(&mut self.show_controls)
+                    .resizable(false)
+                    .vscroll(true)
+                    .show(ctx, |ui| {
+                        let map = &mut self.input_map;
+                        egui::Grid::new("bindings").striped(true).show(ui, |ui| {
+                            for action in Action::ALL {
+                                ui.label(action.name());
+                                ui.horizontal(|ui| {
+                                    let mut unbind = None;
+                                    for (i, binding) in map.bindings(action).iter().enumerate() {
+                                        if ui
+                                            .button(binding.to_string())
+                                            .on_hover_text("Click to unbind")
+                                            .clicked()
+                                        {
+                                            unbind = Some(i);
+                                        }
+                                    }
+                                    if let Some(i) = unbind {
+                                        map.unbind(action, i);
+                                    }
+
+                                    let waiting = self.rebinding == Some(action);
+                                    let label = if waiting { "Press something..." } else { "+" };
+                                    if ui.selectable_label(waiting, label).clicked() {
+                                        self.rebinding = (!waiting).then_some(action);
+                                    }
+                                });
+                                ui.end_row();
+                            }
+                        });
+                        ui.label(
+                            "Escape gives up on binding, modifiers bind on their own when let go",
+                        );
+                        ui.separator();
+
+                        ui.horizontal(|ui| {
+                            ui.label("Bindings:");
+                            ui.text_edit_singleline(&mut self.bindings_path);
+                            if ui.button("Save").clicked() {
+                                self.file_status = Some(match map.save(&self.bindings_path) {
+                                    Ok(()) => format!("saved bindings to {}", self.bindings_path),
+                                    Err(e) => format!("{e:#}"),
+                                });
+                            }
+                            if ui.button("Load").clicked() {
+                                self.file_status =
+                                    Some(match InputMap::load(&self.bindings_path) {
+                                        Ok(loaded) => {
+                                            *map = loaded;
+                                            format!("loaded bindings from {}", self.bindings_path)
+                                        }
+                                        Err(e) => format!("{e:#}"),
+                                    });
+                            }
+                            if ui.button("Defaults").clicked() {
+                                *map = InputMap::default();
+                            }
+                        });
+                        if let Some(status) = &self.file_status {
+                            ui.label(status);
+                        }
+                    });
+                // closing the window gives up on binding
+                if !self.show_controls {
+                    self.rebinding = None;
+                }
+
+                if self.selection.is_empty() {
+                    return;
+                }
+                let mut open = true;
+                egui::Window::new("Inspector")
+                    .open(&mut open)
+                    .resizable(false)
+                    .show(ctx, |ui| {
+                        let simulation = &mut self.nbody_simulation;
+                        if let Some(index) = self.selection.single() {
+                            if let Some(p) = simulation.particles.get_mut(index) {
+                                ui.label(format!("Particle {index}"));
+                                if inspect_particle(ui, p) {
+                                    simulation.particles_edited();
+                                }
+                            }
+                        } else {
+                            ui.label(format!("{} particles", self.selection.len()));
+                        }
+                        ui.separator();
+
+                        let selection = &mut self.selection;
+                        ui.horizontal(|ui| {
+                            let mut color = self.group_color.to_array();
+                            if ui.color_edit_button_rgb(&mut color).changed() {
+                                self.group_color = color.into();
+                            }
+                            if ui.button("Recolor").clicked() {
+                                selection.recolor(simulation, self.group_color);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut self.group_velocity.x).speed(0.1));
+                            ui.add(egui::DragValue::new(&mut self.group_velocity.y).speed(0.1));
+                            if ui.button("Set velocity").clicked() {
+                                selection.set_velocity(simulation, self.group_velocity);
+                            }
+                            if ui.button("Add velocity").clicked() {
+                                selection.add_velocity(simulation, self.group_velocity);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.button("Pin").clicked() {
+                                selection.set_pinned(simulation, true);
+                            }
+                            if ui.button("Unpin").clicked() {
+                                selection.set_pinned(simulation, false);
+                            }
+                            if ui.button("Duplicate").clicked() {
+                                // next to the original so they don't land on top of each other
+                                if let Some((min, max)) = selection.bounds(&simulation.particles) {
+                                    let offset = glam::Vec2::new((max.x - min.x) * 1.1, 0.0);
+                                    selection.duplicate(simulation, offset);
+                                }
+                            }
+                            if ui.button("Delete").clicked() {
+                                selection.delete(simulation);
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            ui.text_edit_singleline(&mut self.stamp_path);
+                            if ui.button("Save stamp").clicked() {
+                                let stamp = selection.stamp(&simulation.particles);
+                                self.file_status =
+                                    Some(match snapshot::export_file(&stamp, &self.stamp_path) {
+                                        Ok(()) => format!("saved a stamp to {}", self.stamp_path),
+                                        Err(e) => format!("{e:#}"),
+                                    });
+                                self.stamp = Some(stamp);
+                            }
+                        });
+                        if let Some(status) = &self.file_status {
+                            ui.label(status);
+                        }
+                    });
+                if !open {
+                    self.selection.clear();
+                }
             },
         );
 
         self.queue.submit(std::iter::once(encoder.finish()));
         output.present();
+
+        if reset {
+            self.reset_simulation();
+        }
+        if save_scene {
+            self.save_scene();
+        }
+        if load_scene {
+            self.load_scene();
+        }
+        if export_particles {
+            self.export_particles();
+        }
+        if import_particles {
+            self.import_particles();
+        }
+        if toggle_replay {
+            self.toggle_replay();
+        }
+        if fit_all || fit_selection {
+            self.zoom_to_fit(fit_selection);
+        }
+        if let Some(angle) = camera_angle {
+            self.camera.set_angle(angle);
+        }
+        if toggle_recording {
+            if self.recorder.is_some() {
+                self.stop_recording();
+            } else if let Err(e) = self.start_recording() {
+                self.file_status = Some(format!("{e:#}"));
+            }
+        }
         // self.last_render = Instant::now();
 
         Ok(())
     }
 
+    /// Recreates the simulation from `seed`, same seed same simulation. Goes back to the imported
+    /// particles instead if there are any
+    fn reset_simulation(&mut self) {
+        self.stop_recording();
+        self.nbody_simulation = match &self.imported {
+            Some(particles) => NBodySimulation::from_particles(particles.clone()),
+            None => create_simulation(self.scenario, self.particle_count, self.seed),
+        };
+        self.clock.reset();
+        self.selection.clear();
+    }
+
+    fn save_scene(&mut self) {
+        let scene = Scene::capture(&self.nbody_simulation, &self.clock, &self.camera);
+        self.file_status = Some(match scene.save(&self.scene_path) {
+            Ok(()) => format!("saved {}", self.scene_path),
+            Err(e) => format!("{e:#}"),
+        });
+    }
+
+    fn load_scene(&mut self) {
+        self.stop_recording();
+        self.file_status = Some(match Scene::load(&self.scene_path) {
+            Ok(scene) => {
+                self.nbody_simulation = scene.restore(&mut self.clock, &mut self.camera);
+                self.selection.clear();
+                format!("loaded {}", self.scene_path)
+            }
+            Err(e) => format!("{e:#}"),
+        });
+    }
+
+    fn export_particles(&mut self) {
+        self.file_status = Some(
+            match snapshot::export_file(&self.nbody_simulation.particles, &self.particles_path) {
+                Ok(()) => format!("exported {}", self.particles_path),
+                Err(e) => format!("{e:#}"),
+            },
+        );
+    }
+
+    fn import_particles(&mut self) {
+        let particles = ColumnMapping::parse(&self.columns)
+            .and_then(|mapping| snapshot::import_file(&self.particles_path, &mapping));
+        self.file_status = Some(match particles {
+            Ok(particles) => {
+                let status = format!("imported {} particles", particles.len());
+                self.imported = Some(particles);
+                self.reset_simulation();
+                status
+            }
+            Err(e) => format!("{e:#}"),
+        });
+    }
+
+    /// Starts recording to `trajectory_path`, the current state is the first frame
+    fn start_recording(&mut self) -> anyhow::Result<()> {
+        let header = TrajectoryHeader {
+            every: self.record_every,
+            timestep: self.clock.step,
+            substeps: self.clock.substeps,
+        };
+        let mut recorder = TrajectoryRecorder::create(&self.trajectory_path, header)?;
+        recorder.record(&self.nbody_simulation)?;
+        self.recorder = Some(recorder);
+        self.file_status = Some(format!("recording to {}", self.trajectory_path));
+        Ok(())
+    }
+
+    /// Recording across a reset would make time jump backwards, so anything that replaces the
+    /// simulation stops it first
+    fn stop_recording(&mut self) {
+        let Some(mut recorder) = self.recorder.take() else {
+            return;
+        };
+        self.file_status = Some(match recorder.flush() {
+            Ok(()) => format!(
+                "recorded {} frames to {}",
+                recorder.frames(),
+                self.trajectory_path
+            ),
+            Err(e) => format!("{e:#}"),
+        });
+    }
+
+    /// Opens `trajectory_path` for playback, or goes back to the simulation if already replaying
+    fn toggle_replay(&mut self) {
+        if self.replay.take().is_some() {
+            return;
+        }
+        // can't replay a file that's still being written
+        self.stop_recording();
+        match Replay::open(&self.trajectory_path) {
+            Ok(replay) => {
+                self.replay = Some(replay);
+                self.selection.clear();
+            }
+            Err(e) => self.file_status = Some(format!("{e:#}")),
+        }
+    }
+
+    fn record(&mut self) {
+        let Some(recorder) = &mut self.recorder else {
+            return;
+        };
+        if let Err(e) = recorder.record(&self.nbody_simulation) {
+            self.file_status = Some(format!("recording stopped: {e:#}"));
+            self.recorder = None;
+        }
+    }
+
+    /// Follows whatever `camera_mode` says, then moves and zooms the camera
+    fn update_camera(&mut self, delta: f32) {
+        // moving by hand takes over
+        if self.camera_controller.is_moving() {
+            self.camera_mode = CameraMode::Free;
+        }
+
+        // follows what's drawn, the interpolated positions, the ones from the last step would
+        // jitter against the particles
+        let positions = |i: usize| self.instances.get(i).map(|instance| instance.position);
+        let target = match self.camera_mode {
+            CameraMode::Free => None,
+            CameraMode::Selection => self
+                .selection
+                .center_of_mass(&self.shown_simulation().particles, positions),
+            CameraMode::CenterOfMass => {
+                let particles = &self.shown_simulation().particles;
+                let (weighted, mass) = (0..particles.len())
+                    .filter_map(|i| Some((positions(i)?, particles[i].mass)))
+                    .fold(
+                        (glam::Vec2::ZERO, 0.0),
+                        |(weighted, total), (position, mass)| {
+                            (weighted + position * mass, total + mass)
+                        },
+                    );
+                (mass > 0.0).then(|| weighted / mass)
+            }
+        };
+        match (self.camera_mode, target) {
+            // could still be heading somewhere `zoom_to_fit` sent it
+            (CameraMode::Free, _) => {}
+            (_, Some(target)) => self.camera_controller.follow(&self.camera, target),
+            // nothing selected, wait where it is
+            (_, None) => self.camera_controller.unfollow(),
+        }
+
+        self.camera_controller.process(&mut self.camera, delta);
+        self.camera.update_projection_matrix();
+    }
+
+    /// Switches to `mode`, or back to free if it's already on
+    fn toggle_camera_mode(&mut self, mode: CameraMode) {
+        self.camera_mode = if self.camera_mode == mode {
+            CameraMode::Free
+        } else {
+            mode
+        };
+        self.camera_controller.unfollow();
+    }
+
+    /// Eases the camera to show every particle, or only the selected ones. When following
+    /// something only the zoom changes
+    fn zoom_to_fit(&mut self, selected_only: bool) {
+        let particles = &self.shown_simulation().particles;
+        let bounds = if selected_only {
+            self.selection.bounds(particles)
+        } else {
+            selection::bounds(particles)
+        };
+        let Some((min, max)) = bounds else {
+            return;
+        };
+
+        self.camera_controller
+            .zoom_to(self.camera.zoom_to_fit(min, max));
+        if self.camera_mode == CameraMode::Free {
+            self.camera_controller.unfollow();
+            self.camera_controller
+                .follow(&self.camera, (min + max) * 0.5);
+        }
+    }
+
+    /// The replay while there is one, otherwise the simulation
+    fn shown_simulation(&self) -> &NBodySimulation {
+        match &self.replay {
+            Some(replay) => &replay.simulation,
+            None => &self.nbody_simulation,
+        }
+    }
+
+    /// Puts a ring behind every selected particle, they're drawn first so the particles cover
+    /// all but a few pixels of them
+    fn highlight_selection(&mut self) {
+        if self.selection.is_empty() {
+            return;
+        }
+        let screen_size = self.screen_size();
+        let pixel = self.camera.screen_to_world(glam::Vec2::X, screen_size)
+            - self.camera.screen_to_world(glam::Vec2::ZERO, screen_size);
+
+        let rings: Vec<ParticleInstance> = self
+            .selection
+            .indices()
+            .iter()
+            .filter_map(|&i| self.instances.get(i))
+            .map(|selected| ParticleInstance {
+                color: glam::Vec3::new(1.0, 0.85, 0.2),
+                radius: selected.radius + pixel.length() * 3.0,
+                ..*selected
+            })
+            .collect();
+        self.instances.splice(0..0, rings);
+    }
+
+    /// The box or lasso a selection drag that started at `path[0]` covers right now, in world
+    /// space. The box is lined up with the screen rather than the world
+    fn selection_polygon(&self, path: &[glam::Vec2]) -> Vec<glam::Vec2> {
+        let cursor = self.cursor_world_position();
+        if self.tool == Tool::Lasso {
+            let mut polygon = path.to_vec();
+            polygon.push(cursor);
+            return polygon;
+        }
+
+        let screen_size = self.screen_size();
+        let start = self.camera.world_to_screen(path[0], screen_size);
+        let end = self.mouse_position;
+        [
+            start,
+            glam::Vec2::new(end.x, start.y),
+            end,
+            glam::Vec2::new(start.x, end.y),
+        ]
+        .map(|corner| self.camera.screen_to_world(corner, screen_size))
+        .to_vec()
+    }
+
+    /// Mouse let go after a selection drag. Barely moving counts as clicking on a particle
+    fn finish_selection(&mut self, path: &[glam::Vec2]) {
+        let add = self.actions.is_held(Action::AddToSelection);
+
+        let start = self.camera.world_to_screen(path[0], self.screen_size());
+        if start.distance(self.mouse_position) < CLICK_DISTANCE {
+            match self
+                .nbody_simulation
+                .particle_at(self.cursor_world_position())
+            {
+                Some(i) if add => self.selection.toggle(i),
+                Some(i) => self.selection.select([i], false),
+                None if !add => self.selection.clear(),
+                None => {}
+            }
+            return;
+        }
+
+        let polygon = self.selection_polygon(path);
+        let inside = selection::inside(&self.nbody_simulation.particles, &polygon);
+        self.selection.select(inside, add);
+    }
+
+    fn screen_size(&self) -> glam::Vec2 {
+        glam::Vec2::new(self.size.width as f32, self.size.height as f32)
+    }
+
+    /// Where the mouse is pointing in the simulation
+    fn cursor_world_position(&self) -> glam::Vec2 {
+        self.camera
+            .screen_to_world(self.mouse_position, self.screen_size())
+    }
+
     fn recreate_instance_buffer(&mut self) {
         self.instance_buffer = self
             .device
@@ -516,11 +1873,172 @@ fn printfps(delta: f32) {
     println!("{} FPS", fps as i32)
 }
 
-fn create_simulation() -> NBodySimulation {
-    let area = 10.0;
-    NBodySimulation::rand_distribute(
-        glam::Vec2 { x: area, y: area },
-        glam::Vec2 { x: -area, y: -area },
-        3,
+fn create_simulation(scenario: Scenario, particle_count: usize, seed: u64) -> NBodySimulation {
+    let mut rng = StdRng::seed_from_u64(seed);
+    scenario.generate(particle_count, &mut rng)
+}
+
+fn camera_bind_group_layout(device: &wgpu::Device) -> wgpu::BindGroupLayout {
+    device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+        label: Some("camera_bind_group_layout"),
+        entries: &[wgpu::BindGroupLayoutEntry {
+            binding: 0,
+            visibility: wgpu::ShaderStages::VERTEX,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        }],
+    })
+}
+
+fn camera_bind_group(
+    device: &wgpu::Device,
+    layout: &wgpu::BindGroupLayout,
+    camera_buffer: &wgpu::Buffer,
+) -> wgpu::BindGroup {
+    device.create_bind_group(&wgpu::BindGroupDescriptor {
+        label: Some("camera_bind_group"),
+        layout,
+        entries: &[wgpu::BindGroupEntry {
+            binding: 0,
+            resource: camera_buffer.as_entire_binding(),
+        }],
+    })
+}
+
+/// The particles are one triangle per `ParticleInstance`, shared by the window and the offscreen
+/// renderer so both look the same
+fn particle_pipeline_builder<'a>(
+    device: &wgpu::Device,
+    camera_bind_group_layout: wgpu::BindGroupLayout,
+    format: wgpu::TextureFormat,
+) -> RenderPipelineBuilder<'a> {
+    let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+        label: Some("shader"),
+        source: wgpu::ShaderSource::Wgsl(
+            include_str!(crate_path!("assets/shaders/shader.wgsl")).into(),
+        ),
+    });
+
+    RenderPipelineBuilder::new(
+        device,
+        render_pipeline::ShaderCollection {
+            shaders: vec![shader],
+            ..Default::default()
+        },
+        vec![ParticleInstance::desc()],
+        vec![camera_bind_group_layout],
+        format,
+        wgpu::PrimitiveTopology::TriangleList,
+        None,
     )
 }
+
+/// Clears `view` to `clear_color`, then the grid and the particles on top of it
+#[allow(clippy::too_many_arguments)]
+fn draw_world(
+    encoder: &mut wgpu::CommandEncoder,
+    view: &wgpu::TextureView,
+    clear_color: wgpu::Color,
+    grid_renderer: &engine::rendering::grid_renderer::GridRenderer,
+    pipeline: &wgpu::RenderPipeline,
+    camera_bind_group: &wgpu::BindGroup,
+    instance_buffer: &wgpu::Buffer,
+    instance_count: u32,
+) {
+    grid_renderer.draw(encoder, view, camera_bind_group, clear_color);
+    // an empty buffer can't be bound
+    if instance_count == 0 {
+        return;
+    }
+
+    let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+        label: Some("Render Pass"),
+        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+            view,
+            resolve_target: None,
+            ops: wgpu::Operations {
+                load: wgpu::LoadOp::Load,
+                store: wgpu::StoreOp::Store,
+            },
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes: None,
+    });
+
+    render_pass.set_pipeline(pipeline);
+    render_pass.set_bind_group(0, camera_bind_group, &[]);
+    render_pass.set_vertex_buffer(0, instance_buffer.slice(..));
+
+    render_pass.draw(0..3, 0..instance_count);
+}
+
+/// The Inspector's fields for a single particle, returns whether it changed in a way the
+/// simulation has to know about, anything but the color
+fn inspect_particle(ui: &mut egui::Ui, p: &mut Particle) -> bool {
+    let mut moved = false;
+    egui::Grid::new("inspector").show(ui, |ui| {
+        ui.label("Position");
+        moved |= ui
+            .add(egui::DragValue::new(&mut p.position.x).speed(0.1))
+            .changed();
+        moved |= ui
+            .add(egui::DragValue::new(&mut p.position.y).speed(0.1))
+            .changed();
+        ui.end_row();
+
+        // pinned particles can't move
+        ui.label("Velocity");
+        ui.add_enabled(
+            !p.pinned,
+            egui::DragValue::new(&mut p.velocity.x).speed(0.1),
+        );
+        ui.add_enabled(
+            !p.pinned,
+            egui::DragValue::new(&mut p.velocity.y).speed(0.1),
+        );
+        ui.end_row();
+
+        ui.label("Color");
+        let mut color = p.color.to_array();
+        if ui.color_edit_button_rgb(&mut color).changed() {
+            p.color = color.into();
+        }
+        ui.end_row();
+
+        // a bigger particle can cover cells it wasn't in before too
+        ui.label("Radius");
+        moved |= ui
+            .add(
+                egui::DragValue::new(&mut p.radius)
+                    .speed(0.01)
+                    .range(0.001..=f32::MAX),
+            )
+            .changed();
+        ui.end_row();
+
+        ui.label("Mass");
+        moved |= ui
+            .add(
+                egui::DragValue::new(&mut p.mass)
+                    .speed(0.1)
+                    .range(0.001..=f32::MAX),
+            )
+            .changed();
+        ui.end_row();
+
+        ui.label("Pinned");
+        if ui.checkbox(&mut p.pinned, "").changed() {
+            if p.pinned {
+                p.velocity = glam::Vec2::ZERO;
+            }
+            moved = true;
+        }
+        ui.end_row();
+    });
+    moved
+}
@@ -1,7 +1,26 @@
 use pollster::FutureExt;
-use rpenguin::run;
+use rpenguin::{batch, cli, run};
 
 fn main() {
-    // pollster::block_on(run());
-    run().block_on();
+    let settings = match cli::Settings::from_args(std::env::args().skip(1)) {
+        Ok(settings) => settings,
+        Err(e) => {
+            eprintln!("{e:#}\n\n{}", cli::USAGE);
+            std::process::exit(1);
+        }
+    };
+    if settings.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    let result = match &settings.batch {
+        Some(batch) => batch::run(&settings, batch),
+        // pollster::block_on(run());
+        None => run(settings).block_on(),
+    };
+    if let Err(e) = result {
+        eprintln!("{e:#}");
+        std::process::exit(1);
+    }
 }
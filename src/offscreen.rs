@@ -0,0 +1,235 @@
+use anyhow::{anyhow, Context};
+use egui_wgpu::wgpu;
+use egui_wgpu::wgpu::util::DeviceExt;
+use std::path::Path;
+
+use crate::engine::camera::{Camera2D, CameraUniform};
+use crate::engine::rendering::grid_renderer::GridRenderer;
+use crate::particle::simulation::ParticleInstance;
+
+/// What the frames are rendered as, `image` wants rgba8 anyway
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba8Unorm;
+
+/// Renders the same grid and particles as the window into a texture nobody sees and reads the
+/// pixels back, for making videos out of batch runs on machines without a display
+pub struct OffscreenRenderer {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    /// which gpu (or llvmpipe/warp) ended up doing the work
+    pub adapter: wgpu::AdapterInfo,
+    width: u32,
+    height: u32,
+    texture: wgpu::Texture,
+    /// copies have to use rows that are a multiple of 256 bytes, the padding is cut off again
+    /// when reading
+    readback: wgpu::Buffer,
+    padded_bytes_per_row: u32,
+
+    grid_renderer: GridRenderer,
+    pipeline: wgpu::RenderPipeline,
+    camera_buffer: wgpu::Buffer,
+    camera_bind_group: wgpu::BindGroup,
+    clear_color: wgpu::Color,
+}
+
+impl OffscreenRenderer {
+    /// Any backend will do. Tries a real gpu first and falls back to the software adapter if
+    /// there isn't one, `software` skips straight to it
+    pub async fn new(width: u32, height: u32, software: bool) -> anyhow::Result<Self> {
+        if width == 0 || height == 0 {
+            anyhow::bail!("frames have to be at least 1x1");
+        }
+
+        let instance = wgpu::Instance::new(wgpu::InstanceDescriptor {
+            backends: wgpu::Backends::all(),
+            ..Default::default()
+        });
+
+        let mut adapter = None;
+        for force_fallback_adapter in [software, true] {
+            adapter = instance
+                .request_adapter(&wgpu::RequestAdapterOptions {
+                    power_preference: wgpu::PowerPreference::HighPerformance,
+                    compatible_surface: None,
+                    force_fallback_adapter,
+                })
+                .await;
+            if adapter.is_some() {
+                break;
+            }
+        }
+        let adapter = adapter.ok_or_else(|| anyhow!("couldn't find any adapter to render with"))?;
+
+        let (device, queue) = adapter
+            .request_device(
+                &wgpu::DeviceDescriptor {
+                    label: Some("Offscreen device"),
+                    required_features: wgpu::Features::empty(),
+                    // software adapters don't always reach the defaults
+                    required_limits: wgpu::Limits::downlevel_defaults()
+                        .using_resolution(adapter.limits()),
+                },
+                None,
+            )
+            .await
+            .context("couldn't open the adapter")?;
+
+        let max = device.limits().max_texture_dimension_2d;
+        if width > max || height > max {
+            anyhow::bail!("frames can be at most {max}x{max} on this adapter");
+        }
+
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Offscreen frame"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        });
+
+        let padded_bytes_per_row = (width * 4).next_multiple_of(wgpu::COPY_BYTES_PER_ROW_ALIGNMENT);
+        let readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Offscreen readback"),
+            size: padded_bytes_per_row as u64 * height as u64,
+            usage: wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        let camera_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Camera projection matrix"),
+            size: std::mem::size_of::<CameraUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let camera_bind_group_layout = crate::camera_bind_group_layout(&device);
+        let camera_bind_group =
+            crate::camera_bind_group(&device, &camera_bind_group_layout, &camera_buffer);
+
+        let grid_renderer = GridRenderer::new(&device, FORMAT);
+        let pipeline = crate::particle_pipeline_builder(&device, camera_bind_group_layout, FORMAT)
+            .build(&device);
+
+        Ok(Self {
+            device,
+            queue,
+            adapter: adapter.get_info(),
+            width,
+            height,
+            texture,
+            readback,
+            padded_bytes_per_row,
+            grid_renderer,
+            pipeline,
+            camera_buffer,
+            camera_bind_group,
+            clear_color: wgpu::Color::BLACK,
+        })
+    }
+
+    /// Width over height, the camera should use this so particles stay round
+    pub fn aspect(&self) -> f32 {
+        self.width as f32 / self.height as f32
+    }
+
+    /// Draws one frame and waits for the pixels
+    pub fn render(
+        &self,
+        camera: &Camera2D,
+        instances: &[ParticleInstance],
+    ) -> anyhow::Result<image::RgbaImage> {
+        self.queue.write_buffer(
+            &self.camera_buffer,
+            0,
+            bytemuck::cast_slice(&[camera.uniform()]),
+        );
+        let instance_buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some("Instance Buffer"),
+                contents: bytemuck::cast_slice(instances),
+                usage: wgpu::BufferUsages::VERTEX,
+            });
+
+        let view = self
+            .texture
+            .create_view(&wgpu::TextureViewDescriptor::default());
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor {
+                label: Some("Offscreen Encoder"),
+            });
+
+        crate::draw_world(
+            &mut encoder,
+            &view,
+            self.clear_color,
+            &self.grid_renderer,
+            &self.pipeline,
+            &self.camera_bind_group,
+            &instance_buffer,
+            instances.len() as u32,
+        );
+
+        encoder.copy_texture_to_buffer(
+            self.texture.as_image_copy(),
+            wgpu::ImageCopyBuffer {
+                buffer: &self.readback,
+                layout: wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(self.padded_bytes_per_row),
+                    rows_per_image: Some(self.height),
+                },
+            },
+            wgpu::Extent3d {
+                width: self.width,
+                height: self.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        self.queue.submit(Some(encoder.finish()));
+
+        let slice = self.readback.slice(..);
+        let (sender, receiver) = std::sync::mpsc::channel();
+        slice.map_async(wgpu::MapMode::Read, move |result| {
+            let _ = sender.send(result);
+        });
+        self.device.poll(wgpu::Maintain::Wait);
+        receiver
+            .recv()
+            .context("the gpu never finished the frame")?
+            .context("couldn't read the frame back")?;
+
+        let row = self.width as usize * 4;
+        let mut pixels = Vec::with_capacity(row * self.height as usize);
+        for padded in slice
+            .get_mapped_range()
+            .chunks_exact(self.padded_bytes_per_row as usize)
+        {
+            pixels.extend_from_slice(&padded[..row]);
+        }
+        self.readback.unmap();
+
+        image::RgbaImage::from_raw(self.width, self.height, pixels)
+            .ok_or_else(|| anyhow!("the frame came back the wrong size"))
+    }
+
+    /// `render` straight into a png
+    pub fn save(
+        &self,
+        camera: &Camera2D,
+        instances: &[ParticleInstance],
+        path: impl AsRef<Path>,
+    ) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        self.render(camera, instances)?
+            .save(path)
+            .with_context(|| format!("couldn't write {}", path.display()))
+    }
+}
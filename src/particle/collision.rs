@@ -0,0 +1,219 @@
+use serde::{Deserialize, Serialize};
+
+use super::simulation::Particle;
+
+/// What happens when two particles overlap
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum CollisionMode {
+    /// Bounce off each other, `restitution` of 1 is perfectly elastic and 0 makes them stick
+    /// together along the normal
+    Elastic { restitution: f32 },
+    /// The lighter particle gets absorbed by the heavier one, keeping total mass, momentum and area
+    Merge,
+    /// Particles pass through each other
+    Ignore,
+}
+
+impl CollisionMode {
+    /// Every mode with some sensible defaults, in the order they show up in the ui
+    pub const ALL: [CollisionMode; 3] = [
+        CollisionMode::Elastic { restitution: 1.0 },
+        CollisionMode::Merge,
+        CollisionMode::Ignore,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            CollisionMode::Elastic { .. } => "Elastic",
+            CollisionMode::Merge => "Merge",
+            CollisionMode::Ignore => "Ignore",
+        }
+    }
+}
+
+impl Default for CollisionMode {
+    fn default() -> Self {
+        CollisionMode::Elastic { restitution: 1.0 }
+    }
+}
+
+#[inline]
+pub fn is_overlapping(a: &Particle, b: &Particle) -> bool {
+    let radii = a.radius + b.radius;
+    a.distance_to_squared(b) <= radii * radii
+}
+
+/// Mutable references to two different particles, `i` has to be smaller than `j`
+#[inline]
+pub fn pair_mut(particles: &mut [Particle], i: usize, j: usize) -> (&mut Particle, &mut Particle) {
+    let (left, right) = particles.split_at_mut(j);
+    (&mut left[i], &mut right[0])
+}
+
+/// Impulse based bounce along the line between the centers, then pushes them apart so they stop
+/// overlapping. Both steps are weighted by mass so momentum and the center of mass don't change,
+/// unless one of them is pinned, that one acts like a wall
+pub fn bounce(a: &mut Particle, b: &mut Particle, restitution: f32) {
+    let a2b = b.position - a.position;
+    let distance = a2b.length();
+    // exactly on top of each other, there is no sensible normal
+    if distance == 0.0 {
+        return;
+    }
+    let normal = a2b / distance;
+    let (inverse_a, inverse_b) = (inverse_mass(a), inverse_mass(b));
+    let inverse_mass = inverse_a + inverse_b;
+    // two pinned particles can't push each other anywhere
+    if inverse_mass == 0.0 {
+        return;
+    }
+
+    let closing_speed = (b.velocity - a.velocity).dot(normal);
+    // only bounce if they are moving towards each other, otherwise they are already separating
+    if closing_speed < 0.0 {
+        let impulse = -(1.0 + restitution) * closing_speed / inverse_mass;
+        a.velocity -= normal * (impulse * inverse_a);
+        b.velocity += normal * (impulse * inverse_b);
+    }
+
+    let overlap = a.radius + b.radius - distance;
+    if overlap > 0.0 {
+        let correction = normal * (overlap / inverse_mass);
+        a.position -= correction * inverse_a;
+        b.position += correction * inverse_b;
+    }
+}
+
+#[inline]
+fn inverse_mass(p: &Particle) -> f32 {
+    if p.pinned {
+        0.0
+    } else {
+        1.0 / p.mass
+    }
+}
+
+/// Perfectly inelastic merge of `from` into `into`, `from` should be removed afterwards. If
+/// either was pinned the result stays pinned where that one was
+pub fn merge(into: &mut Particle, from: &Particle) {
+    let mass = into.mass + from.mass;
+    let (wi, wf) = (into.mass / mass, from.mass / mass);
+
+    match (into.pinned, from.pinned) {
+        (true, _) => into.velocity = glam::Vec2::ZERO,
+        (false, true) => {
+            into.position = from.position;
+            into.velocity = glam::Vec2::ZERO;
+            into.pinned = true;
+        }
+        (false, false) => {
+            into.position = into.position * wi + from.position * wf;
+            into.velocity = into.velocity * wi + from.velocity * wf;
+        }
+    }
+    into.color = into.color * wi + from.color * wf;
+    // keep the total area
+    into.radius = (into.radius * into.radius + from.radius * from.radius).sqrt();
+    into.mass = mass;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Two overlapping particles of different masses heading into each other at an angle
+    fn overlapping_pair() -> (Particle, Particle) {
+        let a = Particle {
+            position: glam::vec2(0.0, 0.0),
+            velocity: glam::vec2(2.0, 0.5),
+            color: glam::Vec3::ONE,
+            radius: 1.0,
+            mass: 3.0,
+            pinned: false,
+        };
+        let b = Particle {
+            position: glam::vec2(1.2, 0.4),
+            velocity: glam::vec2(-1.0, -0.25),
+            color: glam::Vec3::ZERO,
+            radius: 0.5,
+            mass: 1.0,
+            pinned: false,
+        };
+        assert!(is_overlapping(&a, &b));
+        (a, b)
+    }
+
+    fn momentum(particles: &[&Particle]) -> glam::Vec2 {
+        particles.iter().map(|p| p.velocity * p.mass).sum()
+    }
+
+    fn kinetic_energy(particles: &[&Particle]) -> f32 {
+        particles
+            .iter()
+            .map(|p| 0.5 * p.mass * p.velocity.length_squared())
+            .sum()
+    }
+
+    fn center_of_mass(particles: &[&Particle]) -> glam::Vec2 {
+        let mass: f32 = particles.iter().map(|p| p.mass).sum();
+        particles
+            .iter()
+            .map(|p| p.position * p.mass)
+            .sum::<glam::Vec2>()
+            / mass
+    }
+
+    #[test]
+    fn elastic_bounce_conserves_momentum_and_energy() {
+        let (mut a, mut b) = overlapping_pair();
+        let before = (
+            momentum(&[&a, &b]),
+            kinetic_energy(&[&a, &b]),
+            center_of_mass(&[&a, &b]),
+        );
+
+        bounce(&mut a, &mut b, 1.0);
+
+        assert!(momentum(&[&a, &b]).abs_diff_eq(before.0, 1e-5));
+        assert!((kinetic_energy(&[&a, &b]) - before.1).abs() < 1e-5);
+        assert!(center_of_mass(&[&a, &b]).abs_diff_eq(before.2, 1e-5));
+        // pushed apart and separating
+        assert!(a.distance_to_other(&b) >= a.radius + b.radius - 1e-5);
+        assert!((b.velocity - a.velocity).dot(b.position - a.position) > 0.0);
+    }
+
+    #[test]
+    fn inelastic_bounce_conserves_momentum() {
+        for restitution in [0.0, 0.5] {
+            let (mut a, mut b) = overlapping_pair();
+            let before = (
+                momentum(&[&a, &b]),
+                kinetic_energy(&[&a, &b]),
+                center_of_mass(&[&a, &b]),
+            );
+
+            bounce(&mut a, &mut b, restitution);
+
+            assert!(momentum(&[&a, &b]).abs_diff_eq(before.0, 1e-5));
+            assert!(kinetic_energy(&[&a, &b]) < before.1);
+            assert!(center_of_mass(&[&a, &b]).abs_diff_eq(before.2, 1e-5));
+        }
+    }
+
+    #[test]
+    fn merge_conserves_mass_and_momentum() {
+        let (mut a, b) = overlapping_pair();
+        let before = (
+            a.mass + b.mass,
+            momentum(&[&a, &b]),
+            center_of_mass(&[&a, &b]),
+        );
+
+        merge(&mut a, &b);
+
+        assert_eq!(a.mass, before.0);
+        assert!(momentum(&[&a]).abs_diff_eq(before.1, 1e-5));
+        assert!(a.position.abs_diff_eq(before.2, 1e-5));
+        assert!((a.radius * a.radius - (1.0 + 0.25)).abs() < 1e-5);
+    }
+}
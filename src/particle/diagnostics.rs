@@ -0,0 +1,234 @@
+use rayon::prelude::*;
+
+use super::force::Forces;
+use super::simulation::Particle;
+
+/// How many samples `NBodySimulation` keeps around for the plots
+pub const MAX_HISTORY: usize = 2048;
+
+/// Quantities that should stay (roughly) constant if the simulation is behaving, collisions that
+/// aren't perfectly elastic and merges lose kinetic energy on purpose though
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Diagnostics {
+    /// Simulated seconds when this was measured
+    pub time: f64,
+    pub kinetic_energy: f32,
+    pub potential_energy: f32,
+    pub momentum: glam::Vec2,
+    /// Sum of the length of every particle's momentum, what momentum drift is measured against
+    /// since the total momentum is usually ~0
+    pub momentum_scale: f32,
+    /// Around the center of mass, it's 2d so this is just the z component
+    pub angular_momentum: f32,
+    /// Same idea as `momentum_scale`, sum of every particle's angular momentum ignoring the sign
+    pub angular_momentum_scale: f32,
+    /// Mass weighted
+    pub center_of_mass: glam::Vec2,
+}
+
+impl Diagnostics {
+    /// O(n^2) because of the potential energy, the rest is O(n)
+    pub fn measure(particles: &[Particle], forces: &Forces, time: f64) -> Self {
+        let center_of_mass = center_of_mass(particles);
+
+        let (kinetic_energy, momentum, momentum_scale, angular_momentum, angular_momentum_scale) =
+            particles
+                .par_iter()
+                .map(|p| {
+                    let momentum = p.velocity * p.mass;
+                    let angular_momentum = (p.position - center_of_mass).perp_dot(momentum);
+                    (
+                        0.5 * p.mass * p.velocity.length_squared(),
+                        momentum,
+                        momentum.length(),
+                        angular_momentum,
+                        angular_momentum.abs(),
+                    )
+                })
+                .reduce(
+                    || (0.0, glam::Vec2::ZERO, 0.0, 0.0, 0.0),
+                    |a, b| (a.0 + b.0, a.1 + b.1, a.2 + b.2, a.3 + b.3, a.4 + b.4),
+                );
+
+        let potential_energy = (0..particles.len())
+            .into_par_iter()
+            .map(|i| {
+                let pi = &particles[i];
+                particles[i + 1..]
+                    .iter()
+                    .map(|pj| forces.potential(pi.distance_to_other(pj), pi.mass, pj.mass))
+                    .sum::<f32>()
+            })
+            .sum();
+
+        Self {
+            time,
+            kinetic_energy,
+            potential_energy,
+            momentum,
+            momentum_scale,
+            angular_momentum,
+            angular_momentum_scale,
+            center_of_mass,
+        }
+    }
+
+    #[inline]
+    pub fn total_energy(&self) -> f32 {
+        self.kinetic_energy + self.potential_energy
+    }
+
+    /// Change in total energy relative to `reference`
+    pub fn energy_drift(&self, reference: &Self) -> f32 {
+        relative(
+            self.total_energy() - reference.total_energy(),
+            reference.total_energy(),
+        )
+    }
+
+    /// Change in momentum relative to `reference.momentum_scale`
+    pub fn momentum_drift(&self, reference: &Self) -> f32 {
+        relative(
+            (self.momentum - reference.momentum).length(),
+            reference.momentum_scale,
+        )
+    }
+
+    /// Change in angular momentum relative to `reference.angular_momentum_scale`
+    pub fn angular_momentum_drift(&self, reference: &Self) -> f32 {
+        relative(
+            self.angular_momentum - reference.angular_momentum,
+            reference.angular_momentum_scale,
+        )
+    }
+
+    /// Checks how far things have drifted away from `reference`, returns which quantities are past
+    /// their bound
+    pub fn check(&self, reference: &Self, bounds: &ConservationBounds) -> Vec<String> {
+        let mut failed = Vec::new();
+
+        let energy = self.energy_drift(reference);
+        if energy > bounds.energy {
+            failed.push(format!(
+                "energy drifted by {energy:e} (max {:e})",
+                bounds.energy
+            ));
+        }
+        let momentum = self.momentum_drift(reference);
+        if momentum > bounds.momentum {
+            failed.push(format!(
+                "momentum drifted by {momentum:e} (max {:e})",
+                bounds.momentum
+            ));
+        }
+        let angular_momentum = self.angular_momentum_drift(reference);
+        if angular_momentum > bounds.angular_momentum {
+            failed.push(format!(
+                "angular momentum drifted by {angular_momentum:e} (max {:e})",
+                bounds.angular_momentum
+            ));
+        }
+
+        failed
+    }
+}
+
+/// Largest relative drift allowed for each conserved quantity
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ConservationBounds {
+    pub energy: f32,
+    pub momentum: f32,
+    pub angular_momentum: f32,
+}
+
+impl Default for ConservationBounds {
+    fn default() -> Self {
+        Self {
+            energy: 1e-2,
+            momentum: 1e-4,
+            angular_momentum: 1e-2,
+        }
+    }
+}
+
+pub fn center_of_mass(particles: &[Particle]) -> glam::Vec2 {
+    let (weighted, mass) = particles
+        .par_iter()
+        .map(|p| (p.position * p.mass, p.mass))
+        .reduce(|| (glam::Vec2::ZERO, 0.0), |a, b| (a.0 + b.0, a.1 + b.1));
+
+    if mass > 0.0 {
+        weighted / mass
+    } else {
+        glam::Vec2::ZERO
+    }
+}
+
+#[inline]
+fn relative(change: f32, reference: f32) -> f32 {
+    if reference == 0.0 {
+        change.abs()
+    } else {
+        (change / reference).abs()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particle::collision::CollisionMode;
+    use crate::particle::scenarios::Scenario;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Runs a seeded scenario for a couple of simulated seconds, returns whatever went past
+    /// `bounds`
+    fn run(
+        scenario: Scenario,
+        collision_mode: CollisionMode,
+        bounds: &ConservationBounds,
+    ) -> Vec<String> {
+        let mut simulation = scenario.generate(100, &mut StdRng::seed_from_u64(7));
+        simulation.collision_mode = collision_mode;
+        let initial = simulation.measure();
+        for _ in 0..240 {
+            simulation.step(1.0 / 120.0, 4);
+        }
+        simulation.measure().check(&initial, bounds)
+    }
+
+    #[test]
+    fn plummer_sphere_is_conserved() {
+        let failed = run(
+            Scenario::PlummerSphere,
+            CollisionMode::Ignore,
+            &ConservationBounds::default(),
+        );
+        assert!(failed.is_empty(), "{failed:?}");
+    }
+
+    #[test]
+    fn keplerian_disk_is_conserved() {
+        let failed = run(
+            Scenario::KeplerianDisk,
+            CollisionMode::Ignore,
+            &ConservationBounds::default(),
+        );
+        assert!(failed.is_empty(), "{failed:?}");
+    }
+
+    #[test]
+    fn elastic_collisions_conserve_momentum() {
+        // pushing overlapping particles apart changes the potential energy, so only momentum is
+        // expected to hold up here
+        let bounds = ConservationBounds {
+            energy: f32::INFINITY,
+            ..Default::default()
+        };
+        let failed = run(
+            Scenario::PlummerSphere,
+            CollisionMode::Elastic { restitution: 1.0 },
+            &bounds,
+        );
+        assert!(failed.is_empty(), "{failed:?}");
+    }
+}
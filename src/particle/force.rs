@@ -0,0 +1,160 @@
+use serde::{Deserialize, Serialize};
+
+use super::simulation::Particle;
+
+/// Softening used by default, small enough to not matter unless particles are basically touching
+pub const DEFAULT_SOFTENING: f32 = 0.5;
+
+/// Smallest softening that actually gets used, with none at all two particles on top of each
+/// other divide 0 by 0
+pub const MIN_SOFTENING: f32 = 1e-3;
+
+/// How two particles pull on each other
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum ForceLaw {
+    /// Newtonian gravity, G*m1*m2/r^2
+    InverseSquare,
+    /// G*m1*m2/r, what gravity looks like in an actual 2d universe
+    InverseLinear,
+    /// Molecular style 12-6 potential, repulsive closer than `sigma` and attractive a little
+    /// further out with a minimum of `-depth`. Ignores mass and G
+    LennardJones { depth: f32, sigma: f32 },
+    /// Every pair is joined by a spring, k*(r - rest_length). Ignores mass and G
+    Spring { stiffness: f32, rest_length: f32 },
+}
+
+impl ForceLaw {
+    /// Every law with some sensible defaults, in the order they show up in the ui
+    pub const ALL: [ForceLaw; 4] = [
+        ForceLaw::InverseSquare,
+        ForceLaw::InverseLinear,
+        ForceLaw::LennardJones {
+            depth: 50.0,
+            sigma: 2.0,
+        },
+        ForceLaw::Spring {
+            stiffness: 1.0,
+            rest_length: 5.0,
+        },
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ForceLaw::InverseSquare => "Inverse square",
+            ForceLaw::InverseLinear => "Inverse linear",
+            ForceLaw::LennardJones { .. } => "Lennard-Jones",
+            ForceLaw::Spring { .. } => "Spring",
+        }
+    }
+
+    /// Whether the force scales with the mass of the source, only then can a group of particles
+    /// be replaced by their total mass like barnes-hut does
+    pub fn is_mass_weighted(&self) -> bool {
+        matches!(self, ForceLaw::InverseSquare | ForceLaw::InverseLinear)
+    }
+}
+
+/// Everything needed to compute the force between two particles, `Copy` so integrators can carry
+/// it around while the particles are borrowed mutably
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Forces {
+    pub law: ForceLaw,
+    /// G in G*m1*m2/r^2
+    pub gravitational_constant: f32,
+    /// Plummer softening length, r^2 becomes r^2 + softening^2 so gravity stays finite at r = 0.
+    /// Only used by the inverse laws, never goes below `MIN_SOFTENING`
+    pub softening: f32,
+}
+
+impl Default for Forces {
+    fn default() -> Self {
+        Self {
+            law: ForceLaw::InverseSquare,
+            gravitational_constant: 500.0,
+            softening: DEFAULT_SOFTENING,
+        }
+    }
+}
+
+impl Forces {
+    /// `softening^2` with `MIN_SOFTENING` applied
+    #[inline]
+    pub fn softening_squared(&self) -> f32 {
+        let softening = self.softening.max(MIN_SOFTENING);
+        softening * softening
+    }
+
+    /// Force on a body of mass `m1` from a body of mass `m2` that is `offset` away from it
+    #[inline]
+    pub fn force(&self, offset: glam::Vec2, m1: f32, m2: f32) -> glam::Vec2 {
+        let distance_squared = offset.length_squared();
+        let softened = distance_squared + self.softening_squared();
+
+        match self.law {
+            ForceLaw::InverseSquare => {
+                offset * (self.gravitational_constant * m1 * m2 / (softened * softened.sqrt()))
+            }
+            ForceLaw::InverseLinear => offset * (self.gravitational_constant * m1 * m2 / softened),
+            ForceLaw::LennardJones { depth, sigma } => {
+                let s6 = (sigma * sigma / distance_squared).powi(3);
+                // positive is repulsive, hence pointing away from the other body
+                let magnitude = 24.0 * depth * (2.0 * s6 * s6 - s6) / distance_squared;
+                if magnitude.is_finite() {
+                    -offset * magnitude
+                } else {
+                    glam::Vec2::ZERO
+                }
+            }
+            ForceLaw::Spring {
+                stiffness,
+                rest_length,
+            } => {
+                let distance = distance_squared.sqrt();
+                offset.normalize_or_zero() * (stiffness * (distance - rest_length))
+            }
+        }
+    }
+
+    /// Potential energy of a pair `distance` apart, the force is minus its derivative
+    pub fn potential(&self, distance: f32, m1: f32, m2: f32) -> f32 {
+        let distance_squared = distance * distance;
+        let softened = distance_squared + self.softening_squared();
+
+        match self.law {
+            ForceLaw::InverseSquare => -self.gravitational_constant * m1 * m2 / softened.sqrt(),
+            ForceLaw::InverseLinear => 0.5 * self.gravitational_constant * m1 * m2 * softened.ln(),
+            ForceLaw::LennardJones { depth, sigma } => {
+                let s6 = (sigma * sigma / distance_squared).powi(3);
+                4.0 * depth * (s6 * s6 - s6)
+            }
+            ForceLaw::Spring {
+                stiffness,
+                rest_length,
+            } => 0.5 * stiffness * (distance - rest_length).powi(2),
+        }
+    }
+
+    /// Acceleration of `of` due to `towards`
+    #[inline]
+    pub fn acceleration(&self, of: &Particle, towards: &Particle) -> glam::Vec2 {
+        self.force(towards.position - of.position, of.mass, towards.mass) / of.mass
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn coincident_particles_stay_finite_without_softening() {
+        for law in [ForceLaw::InverseSquare, ForceLaw::InverseLinear] {
+            let forces = Forces {
+                law,
+                softening: 0.0,
+                ..Default::default()
+            };
+            assert!(forces.force(glam::Vec2::ZERO, 1.0, 1.0).is_finite());
+            assert!(forces.potential(0.0, 1.0, 1.0).is_finite());
+        }
+    }
+}
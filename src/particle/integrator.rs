@@ -0,0 +1,294 @@
+use rayon::prelude::*;
+
+use super::simulation::Particle;
+
+/// Computes the acceleration of every particle for a given state, integrators that look ahead
+/// call it on a scratch copy of the particles
+pub type AccelerationFn<'a> = dyn Fn(&[Particle]) -> Vec<glam::Vec2> + 'a;
+
+/// Names of the built in integrators, in the order they show up in the ui
+pub const INTEGRATORS: [&str; 4] = ["Symplectic Euler", "Velocity Verlet", "Leapfrog", "RK4"];
+
+/// Advances the positions and velocities of the particles by `delta`
+pub trait Integrator: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn step(&mut self, particles: &mut [Particle], delta: f32, accelerations: &AccelerationFn);
+
+    /// Forgets anything carried over from the last step, called whenever the particles or the
+    /// forces changed in between steps
+    fn invalidate(&mut self) {}
+}
+
+/// Returns a fresh integrator from one of the names in `INTEGRATORS`
+pub fn by_name(name: &str) -> Option<Box<dyn Integrator>> {
+    let integrator: Box<dyn Integrator> = match name {
+        "Symplectic Euler" => Box::new(SymplecticEuler),
+        "Velocity Verlet" => Box::new(VelocityVerlet::default()),
+        "Leapfrog" => Box::new(Leapfrog),
+        "RK4" => Box::new(Rk4),
+        _ => return None,
+    };
+    Some(integrator)
+}
+
+fn kick(particles: &mut [Particle], accelerations: &[glam::Vec2], delta: f32) {
+    particles
+        .par_iter_mut()
+        .zip(accelerations.par_iter())
+        .for_each(|(p, acceleration)| p.velocity += *acceleration * delta);
+}
+
+fn drift(particles: &mut [Particle], delta: f32) {
+    particles.par_iter_mut().for_each(|p| {
+        p.position += p.velocity * delta;
+    });
+}
+
+/// Semi-implicit euler, velocity first then position. First order, this is what the simulation
+/// used to hard code
+pub struct SymplecticEuler;
+
+impl Integrator for SymplecticEuler {
+    fn name(&self) -> &'static str {
+        "Symplectic Euler"
+    }
+
+    fn step(&mut self, particles: &mut [Particle], delta: f32, accelerations: &AccelerationFn) {
+        kick(particles, &accelerations(particles), delta);
+        drift(particles, delta);
+    }
+}
+
+/// Second order and symplectic. Reuses the accelerations from the end of the last step so it only
+/// needs one evaluation per step, `invalidate` throws them away when they no longer match
+#[derive(Default)]
+pub struct VelocityVerlet {
+    previous: Vec<glam::Vec2>,
+}
+
+impl Integrator for VelocityVerlet {
+    fn name(&self) -> &'static str {
+        "Velocity Verlet"
+    }
+
+    fn step(&mut self, particles: &mut [Particle], delta: f32, accelerations: &AccelerationFn) {
+        // invalidated, or particles got added or removed since the last step
+        if self.previous.len() != particles.len() {
+            self.previous = accelerations(particles);
+        }
+
+        particles
+            .par_iter_mut()
+            .zip(self.previous.par_iter())
+            .for_each(|(p, acceleration)| {
+                p.position += p.velocity * delta + *acceleration * (0.5 * delta * delta);
+            });
+
+        let next = accelerations(particles);
+        particles
+            .par_iter_mut()
+            .zip(self.previous.par_iter().zip(next.par_iter()))
+            .for_each(|(p, (previous, next))| p.velocity += (*previous + *next) * (0.5 * delta));
+
+        self.previous = next;
+    }
+
+    fn invalidate(&mut self) {
+        self.previous.clear();
+    }
+}
+
+/// Kick-drift-kick leapfrog. Same order as `VelocityVerlet` but evaluates the accelerations twice
+/// instead of caching them, so it stays correct when something else moves the particles between
+/// steps (collisions, the ui)
+pub struct Leapfrog;
+
+impl Integrator for Leapfrog {
+    fn name(&self) -> &'static str {
+        "Leapfrog"
+    }
+
+    fn step(&mut self, particles: &mut [Particle], delta: f32, accelerations: &AccelerationFn) {
+        let half = delta * 0.5;
+        kick(particles, &accelerations(particles), half);
+        drift(particles, delta);
+        kick(particles, &accelerations(particles), half);
+    }
+}
+
+/// Classic fourth order runge-kutta. Very accurate per step but not symplectic, so energy still
+/// slowly drifts over long runs, and it needs four evaluations per step
+pub struct Rk4;
+
+impl Rk4 {
+    /// Moves `scratch` to `base + velocities * h`
+    fn offset(scratch: &mut [Particle], base: &[Particle], velocities: &[glam::Vec2], h: f32) {
+        scratch
+            .par_iter_mut()
+            .zip(base.par_iter().zip(velocities.par_iter()))
+            .for_each(|(s, (b, v))| s.position = b.position + *v * h);
+    }
+
+    /// `base + k * h` for every velocity
+    fn advance(base: &[Particle], k: &[glam::Vec2], h: f32) -> Vec<glam::Vec2> {
+        base.par_iter()
+            .zip(k.par_iter())
+            .map(|(b, k)| b.velocity + *k * h)
+            .collect()
+    }
+}
+
+impl Integrator for Rk4 {
+    fn name(&self) -> &'static str {
+        "RK4"
+    }
+
+    fn step(&mut self, particles: &mut [Particle], delta: f32, accelerations: &AccelerationFn) {
+        let half = delta * 0.5;
+        let mut scratch = particles.to_vec();
+
+        // k*x are the position derivatives (velocities) and k*v the velocity derivatives
+        let k1x: Vec<glam::Vec2> = particles.iter().map(|p| p.velocity).collect();
+        let k1v = accelerations(particles);
+
+        Self::offset(&mut scratch, particles, &k1x, half);
+        let k2x = Self::advance(particles, &k1v, half);
+        let k2v = accelerations(&scratch);
+
+        Self::offset(&mut scratch, particles, &k2x, half);
+        let k3x = Self::advance(particles, &k2v, half);
+        let k3v = accelerations(&scratch);
+
+        Self::offset(&mut scratch, particles, &k3x, delta);
+        let k4x = Self::advance(particles, &k3v, delta);
+        let k4v = accelerations(&scratch);
+
+        let sixth = delta / 6.0;
+        particles.par_iter_mut().enumerate().for_each(|(i, p)| {
+            p.position += (k1x[i] + 2.0 * k2x[i] + 2.0 * k3x[i] + k4x[i]) * sixth;
+            p.velocity += (k1v[i] + 2.0 * k2v[i] + 2.0 * k3v[i] + k4v[i]) * sixth;
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particle::collision::CollisionMode;
+    use crate::particle::diagnostics::Diagnostics;
+    use crate::particle::force::{ForceLaw, Forces};
+    use crate::particle::simulation::{self, GravitySolver, NBodySimulation};
+
+    const FORCES: Forces = Forces {
+        law: ForceLaw::InverseSquare,
+        gravitational_constant: 1.0,
+        softening: 0.01,
+    };
+
+    /// Two equal masses on a circular orbit around their center of mass, and how long one orbit
+    /// takes
+    fn binary() -> (Vec<Particle>, f32) {
+        let (mass, radius) = (1.0, 1.0);
+        let separation = 2.0 * radius;
+        let softened = separation * separation + FORCES.softening * FORCES.softening;
+        let force =
+            FORCES.gravitational_constant * mass * mass * separation / (softened * softened.sqrt());
+        let speed = (force * radius / mass).sqrt();
+
+        let particle = |side: f32| Particle {
+            position: glam::Vec2::new(side * radius, 0.0),
+            velocity: glam::Vec2::new(0.0, side * speed),
+            mass,
+            ..Default::default()
+        };
+        let period = std::f32::consts::TAU * radius / speed;
+        (vec![particle(1.0), particle(-1.0)], period)
+    }
+
+    fn accelerations(particles: &[Particle]) -> Vec<glam::Vec2> {
+        simulation::accelerations(particles, GravitySolver::DirectSum, &FORCES)
+    }
+
+    fn energy(particles: &[Particle]) -> f32 {
+        Diagnostics::measure(particles, &FORCES, 0.0).total_energy()
+    }
+
+    /// Largest relative energy error over 20 orbits at 200 steps per orbit
+    fn energy_drift(integrator: &mut dyn Integrator) -> f32 {
+        let (mut particles, period) = binary();
+        let delta = period / 200.0;
+        let initial = energy(&particles);
+
+        let mut drift: f32 = 0.0;
+        for _ in 0..20 * 200 {
+            integrator.step(&mut particles, delta, &accelerations);
+            drift = drift.max(((energy(&particles) - initial) / initial).abs());
+        }
+        drift
+    }
+
+    #[test]
+    fn energy_drift_over_long_runs() {
+        let drifts: Vec<(&str, f32)> = INTEGRATORS
+            .iter()
+            .map(|name| {
+                let mut integrator = by_name(name).unwrap();
+                (*name, energy_drift(integrator.as_mut()))
+            })
+            .collect();
+        let drift = |name| drifts.iter().find(|(n, _)| *n == name).unwrap().1;
+
+        // the second order ones should beat euler by a lot
+        assert!(drift("Symplectic Euler") < 5e-3, "{drifts:?}");
+        assert!(drift("Velocity Verlet") < 1e-4, "{drifts:?}");
+        assert!(drift("Leapfrog") < 1e-4, "{drifts:?}");
+        assert!(drift("RK4") < 1e-4, "{drifts:?}");
+        assert!(
+            drift("Velocity Verlet") * 10.0 < drift("Symplectic Euler"),
+            "{drifts:?}"
+        );
+        assert!(
+            drift("Leapfrog") * 10.0 < drift("Symplectic Euler"),
+            "{drifts:?}"
+        );
+    }
+
+    #[test]
+    fn velocity_verlet_invalidates() {
+        let (mut particles, period) = binary();
+        let delta = period / 200.0;
+        let mut cached = VelocityVerlet::default();
+        cached.step(&mut particles, delta, &accelerations);
+
+        // moved by hand between steps
+        particles[0].position.x += 0.5;
+        let mut fresh_particles = particles.clone();
+
+        cached.invalidate();
+        cached.step(&mut particles, delta, &accelerations);
+        VelocityVerlet::default().step(&mut fresh_particles, delta, &accelerations);
+        assert_eq!(particles, fresh_particles);
+    }
+
+    #[test]
+    fn changing_forces_invalidates() {
+        let (particles, period) = binary();
+        let delta = period / 200.0;
+        let mut simulation = NBodySimulation::from_particles(particles);
+        simulation.forces = FORCES;
+        // bouncing would invalidate it anyway
+        simulation.collision_mode = CollisionMode::Ignore;
+        simulation.update(delta);
+
+        simulation.forces.gravitational_constant *= 4.0;
+        let mut fresh = NBodySimulation::from_particles(simulation.particles.clone());
+        fresh.forces = simulation.forces;
+        fresh.collision_mode = CollisionMode::Ignore;
+        fresh.time = simulation.time;
+
+        simulation.update(delta);
+        fresh.update(delta);
+        assert_eq!(simulation.particles, fresh.particles);
+    }
+}
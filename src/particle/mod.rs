@@ -1 +1,11 @@
+pub mod collision;
+pub mod diagnostics;
+pub mod force;
+pub mod integrator;
+pub mod quadtree;
+pub mod scenarios;
 pub mod simulation;
+pub mod snapshot;
+pub mod spatial_hash;
+pub mod spawner;
+pub mod trajectory;
@@ -0,0 +1,221 @@
+use rayon::prelude::*;
+
+use super::force::Forces;
+use super::simulation::Particle;
+
+/// Past this depth coincident particles share a leaf instead of subdividing forever
+const MAX_DEPTH: usize = 24;
+
+/// Sentinel for "no particle" in the leaf body lists
+const NONE: usize = usize::MAX;
+
+struct Node {
+    center: glam::Vec2,
+    half_size: f32,
+
+    mass: f32,
+    mass_center: glam::Vec2,
+
+    /// index of the first of 4 consecutive children, 0 if this is a leaf (the root can never be a
+    /// child so 0 is free)
+    children: usize,
+    /// first particle in this leaf, the rest are chained through `QuadTree::next`
+    body: usize,
+}
+
+impl Node {
+    fn new(center: glam::Vec2, half_size: f32) -> Self {
+        Self {
+            center,
+            half_size,
+            mass: 0.0,
+            mass_center: glam::Vec2::ZERO,
+            children: 0,
+            body: NONE,
+        }
+    }
+
+    #[inline]
+    fn is_leaf(&self) -> bool {
+        self.children == 0
+    }
+
+    #[inline]
+    fn contains(&self, point: glam::Vec2) -> bool {
+        let offset = (point - self.center).abs();
+        offset.x <= self.half_size && offset.y <= self.half_size
+    }
+
+    #[inline]
+    fn quadrant(&self, point: glam::Vec2) -> usize {
+        (point.x >= self.center.x) as usize | (((point.y >= self.center.y) as usize) << 1)
+    }
+}
+
+/// Barnes-Hut quadtree, built from scratch every step since the particles move anyway
+pub struct QuadTree<'a> {
+    particles: &'a [Particle],
+    nodes: Vec<Node>,
+    /// linked list of particles sharing a leaf at `MAX_DEPTH`
+    next: Vec<usize>,
+}
+
+impl<'a> QuadTree<'a> {
+    pub fn new(particles: &'a [Particle]) -> Self {
+        let mut min = glam::Vec2::splat(f32::MAX);
+        let mut max = glam::Vec2::splat(f32::MIN);
+        for p in particles {
+            min = min.min(p.position);
+            max = max.max(p.position);
+        }
+
+        let (center, half_size) = if particles.is_empty() {
+            (glam::Vec2::ZERO, 1.0)
+        } else {
+            // pad it a bit so particles on the edge don't land outside due to rounding
+            (
+                (min + max) * 0.5,
+                ((max - min).max_element() * 0.5).max(1.0) * 1.01,
+            )
+        };
+
+        let mut tree = Self {
+            particles,
+            nodes: Vec::with_capacity(particles.len() * 2 + 1),
+            next: vec![NONE; particles.len()],
+        };
+        tree.nodes.push(Node::new(center, half_size));
+
+        for i in 0..particles.len() {
+            tree.insert(i);
+        }
+        tree.compute_mass();
+
+        tree
+    }
+
+    fn insert(&mut self, index: usize) {
+        let position = self.particles[index].position;
+        let mut node = 0;
+        let mut depth = 0;
+
+        loop {
+            if !self.nodes[node].is_leaf() {
+                node = self.nodes[node].children + self.nodes[node].quadrant(position);
+                depth += 1;
+                continue;
+            }
+
+            let other = self.nodes[node].body;
+            if other == NONE {
+                self.nodes[node].body = index;
+                return;
+            }
+
+            if depth >= MAX_DEPTH {
+                self.next[index] = other;
+                self.nodes[node].body = index;
+                return;
+            }
+
+            // split the leaf and push the particle that was living here down a level
+            self.subdivide(node);
+            let child = self.nodes[node].children
+                + self.nodes[node].quadrant(self.particles[other].position);
+            self.nodes[node].body = NONE;
+            self.nodes[child].body = other;
+        }
+    }
+
+    fn subdivide(&mut self, node: usize) {
+        let center = self.nodes[node].center;
+        let quarter = self.nodes[node].half_size * 0.5;
+
+        self.nodes[node].children = self.nodes.len();
+        // same order as `Node::quadrant`
+        for offset in [
+            glam::Vec2::new(-quarter, -quarter),
+            glam::Vec2::new(quarter, -quarter),
+            glam::Vec2::new(-quarter, quarter),
+            glam::Vec2::new(quarter, quarter),
+        ] {
+            self.nodes.push(Node::new(center + offset, quarter));
+        }
+    }
+
+    /// Children are always pushed after their parents so walking backwards visits them first
+    fn compute_mass(&mut self) {
+        for n in (0..self.nodes.len()).rev() {
+            let (mut mass, mut mass_center) = (0.0, glam::Vec2::ZERO);
+
+            if self.nodes[n].is_leaf() {
+                let mut body = self.nodes[n].body;
+                while body != NONE {
+                    let p = &self.particles[body];
+                    mass += p.mass;
+                    mass_center += p.position * p.mass;
+                    body = self.next[body];
+                }
+            } else {
+                let first = self.nodes[n].children;
+                for child in &self.nodes[first..first + 4] {
+                    mass += child.mass;
+                    mass_center += child.mass_center * child.mass;
+                }
+            }
+
+            let node = &mut self.nodes[n];
+            node.mass = mass;
+            if mass > 0.0 {
+                node.mass_center = mass_center / mass;
+            }
+        }
+    }
+
+    /// Approximates the acceleration of every particle, a node is treated as a single body when
+    /// `node width / distance < theta`. `theta = 0` is the same as the direct sum. Only makes sense
+    /// for mass weighted force laws
+    pub fn accelerations(&self, theta: f32, forces: &Forces) -> Vec<glam::Vec2> {
+        (0..self.particles.len())
+            .into_par_iter()
+            .map(|i| self.acceleration_of(i, theta, forces))
+            .collect()
+    }
+
+    fn acceleration_of(&self, index: usize, theta: f32, forces: &Forces) -> glam::Vec2 {
+        let particle = &self.particles[index];
+        let mut acceleration = glam::Vec2::ZERO;
+        let mut stack = vec![0];
+
+        while let Some(n) = stack.pop() {
+            let node = &self.nodes[n];
+            if node.mass == 0.0 {
+                continue;
+            }
+
+            if node.is_leaf() {
+                let mut body = node.body;
+                while body != NONE {
+                    if body != index {
+                        acceleration += forces.acceleration(particle, &self.particles[body]);
+                    }
+                    body = self.next[body];
+                }
+                continue;
+            }
+
+            let to_mass = node.mass_center - particle.position;
+            let distance = to_mass.length();
+            let width = node.half_size * 2.0;
+
+            // never approximate a node that the particle itself lives in
+            if !node.contains(particle.position) && width < theta * distance {
+                acceleration += forces.force(to_mass, particle.mass, node.mass) / particle.mass;
+            } else {
+                stack.extend(node.children..node.children + 4);
+            }
+        }
+
+        acceleration
+    }
+}
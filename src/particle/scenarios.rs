@@ -0,0 +1,313 @@
+use rand::Rng;
+use std::f32::consts::TAU;
+
+use super::diagnostics::Diagnostics;
+use super::force::Forces;
+use super::simulation::{NBodySimulation, Particle, DEFAULT_DENSITY};
+
+/// Every initial condition generator, so they can be picked from the ui and the command line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scenario {
+    Random,
+    Grid,
+    KeplerianDisk,
+    PlummerSphere,
+    Binaries,
+    GalaxyCollision,
+}
+
+impl Scenario {
+    pub const ALL: [Scenario; 6] = [
+        Scenario::Random,
+        Scenario::Grid,
+        Scenario::KeplerianDisk,
+        Scenario::PlummerSphere,
+        Scenario::Binaries,
+        Scenario::GalaxyCollision,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Scenario::Random => "random",
+            Scenario::Grid => "grid",
+            Scenario::KeplerianDisk => "disk",
+            Scenario::PlummerSphere => "plummer",
+            Scenario::Binaries => "binaries",
+            Scenario::GalaxyCollision => "galaxies",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|s| s.name() == name)
+    }
+
+    /// Particle count that looks good for this scenario
+    pub fn default_count(&self) -> usize {
+        match self {
+            Scenario::Random => 3,
+            Scenario::Grid => 100,
+            Scenario::KeplerianDisk => 500,
+            Scenario::PlummerSphere => 500,
+            Scenario::Binaries => 8,
+            Scenario::GalaxyCollision => 1000,
+        }
+    }
+
+    /// Most particles the scenario can make, `None` if there's no limit. The ui doesn't go past
+    /// it and the cli refuses more
+    pub fn max_count(&self) -> Option<usize> {
+        match self {
+            Scenario::Binaries => Some(1 << MAX_BINARY_LEVELS),
+            _ => None,
+        }
+    }
+
+    pub fn generate(&self, particle_count: usize, rng: &mut impl Rng) -> NBodySimulation {
+        match self {
+            Scenario::Random => {
+                // keep the same density as the original 3 particles in a 10 unit box
+                let area = 10.0 * (particle_count as f32 / 3.0).sqrt().max(1.0);
+                NBodySimulation::rand_distribute(
+                    glam::Vec2::splat(area),
+                    glam::Vec2::splat(-area),
+                    particle_count,
+                    rng,
+                )
+            }
+            Scenario::Grid => {
+                NBodySimulation::grid((particle_count as f32).sqrt().ceil() as usize, 3.0, rng)
+            }
+            Scenario::KeplerianDisk => NBodySimulation::keplerian_disk(particle_count, rng),
+            Scenario::PlummerSphere => NBodySimulation::plummer_sphere(particle_count, rng),
+            Scenario::Binaries => NBodySimulation::binaries(particle_count, rng),
+            Scenario::GalaxyCollision => NBodySimulation::galaxy_collision(particle_count, rng),
+        }
+    }
+}
+
+impl NBodySimulation {
+    /// A heavy particle in the middle with the rest on circular orbits around it
+    pub fn keplerian_disk(particle_count: usize, rng: &mut impl Rng) -> Self {
+        let mut simulation = Self::default();
+        simulation.particles = disk(
+            glam::Vec2::ZERO,
+            glam::Vec2::ZERO,
+            particle_count,
+            &simulation.forces,
+            rng,
+        );
+        simulation
+    }
+
+    /// Cluster following the (projected) plummer density profile, with random velocities scaled
+    /// so the cluster starts out in virial equilibrium
+    pub fn plummer_sphere(particle_count: usize, rng: &mut impl Rng) -> Self {
+        let mut simulation = Self::default();
+        let scale = 2.0 * (particle_count as f32).sqrt().max(1.0);
+        let radius = 0.5;
+
+        let mut particles: Vec<Particle> = (0..particle_count)
+            .map(|_| {
+                // inverse of the cumulative mass M(<R) = R^2 / (R^2 + a^2), capped so the odd
+                // particle doesn't end up miles away
+                let x: f32 = rng.gen_range(0.0..0.95);
+                let distance = scale * (x / (1.0 - x)).sqrt();
+                let dispersion = (distance * distance + scale * scale).powf(-0.25);
+
+                Particle {
+                    position: random_direction(rng) * distance,
+                    velocity: gaussian(rng) * dispersion,
+                    color: random_color(rng),
+                    radius,
+                    mass: Particle::area_mass(radius, DEFAULT_DENSITY),
+                    pinned: false,
+                }
+            })
+            .collect();
+
+        // no drifting off as a whole
+        let drift = particles
+            .iter()
+            .map(|p| p.velocity * p.mass)
+            .sum::<glam::Vec2>()
+            / particles.iter().map(|p| p.mass).sum::<f32>();
+        particles.iter_mut().for_each(|p| p.velocity -= drift);
+
+        // 2K = -U
+        let diagnostics = Diagnostics::measure(&particles, &simulation.forces, 0.0);
+        if diagnostics.kinetic_energy > 0.0 && diagnostics.potential_energy < 0.0 {
+            let scale = (-diagnostics.potential_energy / (2.0 * diagnostics.kinetic_energy)).sqrt();
+            particles.iter_mut().for_each(|p| p.velocity *= scale);
+        }
+
+        simulation.particles = particles;
+        simulation
+    }
+
+    /// Binaries orbiting binaries orbiting binaries, `particle_count` gets rounded down to a power
+    /// of 2, at most `Scenario::max_count` since the inner orbits get too tight past that
+    pub fn binaries(particle_count: usize, rng: &mut impl Rng) -> Self {
+        let levels = (particle_count.max(2) as f32)
+            .log2()
+            .floor()
+            .min(MAX_BINARY_LEVELS as f32) as i32;
+
+        let mut simulation = Self::default();
+        let mut binaries = Binaries {
+            particles: Vec::with_capacity(1 << levels),
+            // small enough that the innermost pairs don't touch
+            radius: (BINARY_SEPARATION * BINARY_RATIO.powi(levels - 1) / 5.0).min(1.0),
+            forces: &simulation.forces,
+            rng,
+        };
+        binaries.add(
+            levels,
+            glam::Vec2::ZERO,
+            glam::Vec2::ZERO,
+            BINARY_SEPARATION,
+        );
+
+        simulation.particles = binaries.particles;
+        simulation
+    }
+
+    /// Two disks on a collision course, slightly offset so it isn't head on
+    pub fn galaxy_collision(particle_count: usize, rng: &mut impl Rng) -> Self {
+        let mut simulation = Self::default();
+        let half = particle_count / 2;
+
+        let offset = glam::Vec2::new(120.0, 20.0);
+        let velocity = glam::Vec2::new(8.0, 0.0);
+        simulation.particles = disk(-offset, velocity, half, &simulation.forces, rng);
+        simulation.particles.extend(disk(
+            offset,
+            -velocity,
+            particle_count - half,
+            &simulation.forces,
+            rng,
+        ));
+        simulation
+    }
+}
+
+/// Speed of a circular orbit at `distance` around `mass`, with softening taken into account
+fn circular_speed(forces: &Forces, mass: f32, distance: f32) -> f32 {
+    let softened = distance * distance + forces.softening_squared();
+    (forces.gravitational_constant * mass * distance * distance / softened.powf(1.5)).sqrt()
+}
+
+/// Central mass plus `particle_count - 1` particles orbiting it
+fn disk(
+    center: glam::Vec2,
+    velocity: glam::Vec2,
+    particle_count: usize,
+    forces: &Forces,
+    rng: &mut impl Rng,
+) -> Vec<Particle> {
+    if particle_count == 0 {
+        return Vec::new();
+    }
+
+    let core_radius = 4.0;
+    let core = Particle {
+        position: center,
+        velocity,
+        color: glam::Vec3::new(1.0, 0.9, 0.6),
+        radius: core_radius,
+        // a lot denser than the rest so the disk doesn't fall apart
+        mass: Particle::area_mass(core_radius, DEFAULT_DENSITY * 20.0),
+        pinned: false,
+    };
+
+    let radius = 0.3;
+    let mass = Particle::area_mass(radius, DEFAULT_DENSITY);
+    let inner = 10.0;
+    let outer = inner + 2.0 * (particle_count as f32).sqrt();
+
+    // uniform over the area of the ring
+    let mut distances: Vec<f32> = (1..particle_count)
+        .map(|_| rng.gen_range(inner * inner..outer * outer).sqrt())
+        .collect();
+    distances.sort_by(|a, b| a.total_cmp(b));
+
+    let mut particles = Vec::with_capacity(particle_count);
+    let mut enclosed = core.mass;
+    particles.push(core);
+
+    for distance in distances {
+        let direction = random_direction(rng);
+        // everything closer in pulls the particle roughly like a point mass would
+        let speed = circular_speed(forces, enclosed, distance);
+        particles.push(Particle {
+            position: center + direction * distance,
+            velocity: velocity + direction.perp() * speed,
+            color: random_color(rng),
+            radius,
+            mass,
+            pinned: false,
+        });
+        enclosed += mass;
+    }
+
+    particles
+}
+
+/// Distance between the two halves of the outermost binary
+const BINARY_SEPARATION: f32 = 80.0;
+/// How much tighter each level is than the one around it
+const BINARY_RATIO: f32 = 0.2;
+/// Past this many levels of binaries the innermost ones touch
+const MAX_BINARY_LEVELS: u32 = 4;
+
+struct Binaries<'a, R: Rng> {
+    particles: Vec<Particle>,
+    radius: f32,
+    forces: &'a Forces,
+    rng: &'a mut R,
+}
+
+impl<R: Rng> Binaries<'_, R> {
+    /// Adds a binary with `2^level` particles in it, halves that are binaries themselves
+    fn add(&mut self, level: i32, center: glam::Vec2, velocity: glam::Vec2, separation: f32) {
+        let mass = Particle::area_mass(self.radius, DEFAULT_DENSITY);
+        if level <= 0 {
+            self.particles.push(Particle {
+                position: center,
+                velocity,
+                color: random_color(self.rng),
+                radius: self.radius,
+                mass,
+                pinned: false,
+            });
+            return;
+        }
+
+        let total_mass = mass * 2.0f32.powi(level);
+        let axis = random_direction(self.rng);
+        // both halves orbit their common center, each going half the relative speed
+        let speed = circular_speed(self.forces, total_mass, separation) * 0.5;
+
+        for side in [-1.0, 1.0] {
+            self.add(
+                level - 1,
+                center + axis * (separation * 0.5 * side),
+                velocity + axis.perp() * (speed * side),
+                separation * BINARY_RATIO,
+            );
+        }
+    }
+}
+
+pub(super) fn random_direction(rng: &mut impl Rng) -> glam::Vec2 {
+    glam::Vec2::from_angle(rng.gen_range(0.0..TAU))
+}
+
+/// Two independent standard normal samples (box-muller)
+pub(super) fn gaussian(rng: &mut impl Rng) -> glam::Vec2 {
+    let u: f32 = rng.gen_range(f32::EPSILON..1.0);
+    random_direction(rng) * (-2.0 * u.ln()).sqrt()
+}
+
+pub(super) fn random_color(rng: &mut impl Rng) -> glam::Vec3 {
+    glam::Vec3::new(rng.gen(), rng.gen(), rng.gen()).normalize_or(glam::Vec3::ONE)
+}
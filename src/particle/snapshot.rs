@@ -0,0 +1,368 @@
+use anyhow::{anyhow, bail, Context};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::Path;
+
+use super::simulation::{Particle, DEFAULT_DENSITY};
+
+/// Plain particle tables for getting data in and out of notebooks, one row per particle
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Csv,
+    /// One json object per line
+    JsonLines,
+}
+
+impl Format {
+    /// Picks the format from the extension, `.csv` or `.jsonl`/`.ndjson`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(Format::Csv),
+            "jsonl" | "ndjson" => Some(Format::JsonLines),
+            _ => None,
+        }
+    }
+}
+
+/// Which column every particle field is read from. Only `x` and `y` have to be there, missing
+/// velocities are 0, missing colors are white, missing `pinned` is false, and whichever of radius
+/// and mass is missing is worked out from the other with `DEFAULT_DENSITY`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnMapping {
+    pub x: String,
+    pub y: String,
+    pub vx: String,
+    pub vy: String,
+    pub r: String,
+    pub g: String,
+    pub b: String,
+    pub radius: String,
+    pub mass: String,
+    /// `true`/`false` or a number, anything but 0 is pinned
+    pub pinned: String,
+}
+
+impl Default for ColumnMapping {
+    /// The names `export` writes
+    fn default() -> Self {
+        Self {
+            x: "x".to_string(),
+            y: "y".to_string(),
+            vx: "vx".to_string(),
+            vy: "vy".to_string(),
+            r: "r".to_string(),
+            g: "g".to_string(),
+            b: "b".to_string(),
+            radius: "radius".to_string(),
+            mass: "mass".to_string(),
+            pinned: "pinned".to_string(),
+        }
+    }
+}
+
+impl ColumnMapping {
+    /// Starts from the defaults and renames fields from a list like `x=pos_x,y=pos_y,mass=m`
+    pub fn parse(spec: &str) -> anyhow::Result<Self> {
+        let mut mapping = Self::default();
+        for pair in spec.split(',').filter(|pair| !pair.trim().is_empty()) {
+            let (field, column) = pair
+                .split_once('=')
+                .ok_or_else(|| anyhow!("expected field=column, got \"{pair}\""))?;
+            let column = column.trim().to_string();
+            match field.trim() {
+                "x" => mapping.x = column,
+                "y" => mapping.y = column,
+                "vx" => mapping.vx = column,
+                "vy" => mapping.vy = column,
+                "r" => mapping.r = column,
+                "g" => mapping.g = column,
+                "b" => mapping.b = column,
+                "radius" => mapping.radius = column,
+                "mass" => mapping.mass = column,
+                "pinned" => mapping.pinned = column,
+                field => bail!("unknown particle field \"{field}\""),
+            }
+        }
+        Ok(mapping)
+    }
+
+    /// Builds a particle out of one row, `get` looks a column up by name
+    fn particle(
+        &self,
+        get: impl Fn(&str) -> anyhow::Result<Option<f32>>,
+    ) -> anyhow::Result<Particle> {
+        let required =
+            |column: &str| get(column)?.ok_or_else(|| anyhow!("missing column \"{column}\""));
+        let optional =
+            |column: &str, default: f32| Ok::<_, anyhow::Error>(get(column)?.unwrap_or(default));
+
+        let (radius, mass) = match (get(&self.radius)?, get(&self.mass)?) {
+            (Some(radius), Some(mass)) => (radius, mass),
+            (Some(radius), None) => (radius, Particle::area_mass(radius, DEFAULT_DENSITY)),
+            (None, Some(mass)) => (
+                (mass / (DEFAULT_DENSITY * std::f32::consts::PI)).sqrt(),
+                mass,
+            ),
+            (None, None) => {
+                let default = Particle::default();
+                (default.radius, default.mass)
+            }
+        };
+        // written this way round so NaN fails too
+        if !(radius > 0.0 && radius.is_finite() && mass > 0.0 && mass.is_finite()) {
+            bail!("radius and mass have to be positive and finite");
+        }
+
+        let particle = Particle {
+            position: glam::Vec2::new(required(&self.x)?, required(&self.y)?),
+            velocity: glam::Vec2::new(optional(&self.vx, 0.0)?, optional(&self.vy, 0.0)?),
+            color: glam::Vec3::new(
+                optional(&self.r, 1.0)?,
+                optional(&self.g, 1.0)?,
+                optional(&self.b, 1.0)?,
+            ),
+            radius,
+            mass,
+            pinned: optional(&self.pinned, 0.0)? != 0.0,
+        };
+        if !(particle.position.is_finite() && particle.velocity.is_finite()) {
+            bail!("position and velocity have to be finite");
+        }
+        Ok(particle)
+    }
+}
+
+/// One particle the way `export` writes it, the names match `ColumnMapping::default`
+#[derive(Serialize)]
+struct Row {
+    x: f32,
+    y: f32,
+    vx: f32,
+    vy: f32,
+    r: f32,
+    g: f32,
+    b: f32,
+    radius: f32,
+    mass: f32,
+    pinned: bool,
+}
+
+impl From<&Particle> for Row {
+    fn from(p: &Particle) -> Self {
+        Self {
+            x: p.position.x,
+            y: p.position.y,
+            vx: p.velocity.x,
+            vy: p.velocity.y,
+            r: p.color.x,
+            g: p.color.y,
+            b: p.color.z,
+            radius: p.radius,
+            mass: p.mass,
+            pinned: p.pinned,
+        }
+    }
+}
+
+pub fn export(particles: &[Particle], format: Format, writer: impl Write) -> anyhow::Result<()> {
+    match format {
+        Format::Csv => {
+            let mut writer = csv::Writer::from_writer(writer);
+            for p in particles {
+                writer.serialize(Row::from(p))?;
+            }
+            writer.flush()?;
+        }
+        Format::JsonLines => {
+            let mut writer = std::io::BufWriter::new(writer);
+            for p in particles {
+                serde_json::to_writer(&mut writer, &Row::from(p))?;
+                writeln!(writer)?;
+            }
+            writer.flush()?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads particles back, errors point at the row that didn't make sense (1 is the first particle)
+pub fn import(
+    reader: impl Read,
+    format: Format,
+    mapping: &ColumnMapping,
+) -> anyhow::Result<Vec<Particle>> {
+    let mut particles = Vec::new();
+
+    match format {
+        Format::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .trim(csv::Trim::All)
+                .from_reader(reader);
+            let header: HashMap<String, usize> = reader
+                .headers()?
+                .iter()
+                .enumerate()
+                .map(|(i, column)| (column.to_string(), i))
+                .collect();
+
+            for (n, record) in reader.records().enumerate() {
+                let record = record?;
+                let get = |column: &str| {
+                    let Some(field) = header.get(column).and_then(|&i| record.get(i)) else {
+                        return Ok(None);
+                    };
+                    // empty cells count as missing
+                    if field.is_empty() {
+                        return Ok(None);
+                    }
+                    if let Ok(flag) = field.parse::<bool>() {
+                        return Ok(Some(if flag { 1.0 } else { 0.0 }));
+                    }
+                    field
+                        .parse()
+                        .map(Some)
+                        .with_context(|| format!("\"{column}\" isn't a number"))
+                };
+                particles.push(
+                    mapping
+                        .particle(get)
+                        .with_context(|| format!("row {}", n + 1))?,
+                );
+            }
+        }
+        Format::JsonLines => {
+            let lines = BufReader::new(reader).lines();
+            for (n, line) in lines.enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let object: Map<String, Value> =
+                    serde_json::from_str(&line).with_context(|| format!("line {}", n + 1))?;
+                let get = |column: &str| match object.get(column) {
+                    None | Some(Value::Null) => Ok(None),
+                    Some(Value::Bool(flag)) => Ok(Some(if *flag { 1.0 } else { 0.0 })),
+                    Some(value) => value
+                        .as_f64()
+                        .map(|value| Some(value as f32))
+                        .ok_or_else(|| anyhow!("\"{column}\" isn't a number")),
+                };
+                particles.push(
+                    mapping
+                        .particle(get)
+                        .with_context(|| format!("line {}", n + 1))?,
+                );
+            }
+        }
+    }
+
+    Ok(particles)
+}
+
+pub fn export_file(particles: &[Particle], path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let format = Format::from_path(path)
+        .ok_or_else(|| anyhow!("{} should end in .csv or .jsonl", path.display()))?;
+    let file = std::fs::File::create(path)
+        .with_context(|| format!("couldn't create {}", path.display()))?;
+    export(particles, format, file)
+}
+
+pub fn import_file(
+    path: impl AsRef<Path>,
+    mapping: &ColumnMapping,
+) -> anyhow::Result<Vec<Particle>> {
+    let path = path.as_ref();
+    let format = Format::from_path(path)
+        .ok_or_else(|| anyhow!("{} should end in .csv or .jsonl", path.display()))?;
+    let file =
+        std::fs::File::open(path).with_context(|| format!("couldn't open {}", path.display()))?;
+    import(file, format, mapping).with_context(|| format!("couldn't import {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn particles() -> Vec<Particle> {
+        vec![
+            Particle {
+                position: glam::vec2(1.5, -2.0),
+                velocity: glam::vec2(0.25, 4.0),
+                color: glam::vec3(1.0, 0.5, 0.0),
+                radius: 2.0,
+                mass: 12.5,
+                pinned: false,
+            },
+            Particle {
+                position: glam::vec2(-10.0, 3.0),
+                velocity: glam::Vec2::ZERO,
+                color: glam::Vec3::ONE,
+                radius: 0.5,
+                mass: 3.0,
+                pinned: true,
+            },
+        ]
+    }
+
+    #[test]
+    fn round_trips() {
+        for format in [Format::Csv, Format::JsonLines] {
+            let mut buffer = Vec::new();
+            export(&particles(), format, &mut buffer).unwrap();
+            let imported = import(&buffer[..], format, &ColumnMapping::default()).unwrap();
+            assert_eq!(imported, particles(), "{format:?}");
+        }
+    }
+
+    #[test]
+    fn pinned_is_optional() {
+        let csv = "x,y,pinned\n0,0,\n1,0,1\n2,0,0\n";
+        let imported = import(csv.as_bytes(), Format::Csv, &ColumnMapping::default()).unwrap();
+        let pinned: Vec<_> = imported.iter().map(|p| p.pinned).collect();
+        assert_eq!(pinned, [false, true, false]);
+
+        let jsonl = "{\"x\": 0, \"y\": 0}\n{\"x\": 1, \"y\": 0, \"pinned\": true}\n";
+        let imported = import(
+            jsonl.as_bytes(),
+            Format::JsonLines,
+            &ColumnMapping::default(),
+        )
+        .unwrap();
+        let pinned: Vec<_> = imported.iter().map(|p| p.pinned).collect();
+        assert_eq!(pinned, [false, true]);
+    }
+
+    #[test]
+    fn rejects_non_finite_values() {
+        for csv in [
+            "x,y\n0,NaN\n",
+            "x,y,vx\n0,0,inf\n",
+            "x,y,radius\n0,0,NaN\n",
+            "x,y,mass\n0,0,-inf\n",
+        ] {
+            assert!(
+                import(csv.as_bytes(), Format::Csv, &ColumnMapping::default()).is_err(),
+                "{csv}"
+            );
+        }
+
+        // json has no NaN, but anything past f32::MAX overflows into infinity
+        for jsonl in [
+            "{\"x\": 1e39, \"y\": 0}",
+            "{\"x\": 0, \"y\": 0, \"mass\": 1e39}",
+        ] {
+            assert!(
+                import(
+                    jsonl.as_bytes(),
+                    Format::JsonLines,
+                    &ColumnMapping::default()
+                )
+                .is_err(),
+                "{jsonl}"
+            );
+        }
+    }
+}
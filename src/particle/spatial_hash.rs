@@ -0,0 +1,206 @@
+use rayon::prelude::*;
+
+use super::simulation::Particle;
+
+type Cell = (i32, i32);
+
+/// Particles covering more cells than this along either axis go in `SpatialHash::large` instead,
+/// one that merged up to hundreds of times the cell size would otherwise add an entry for every
+/// cell it covers
+const MAX_CELL_SPAN: u32 = 8;
+
+/// Uniform grid broadphase, finds the pairs of particles that might be touching in about O(n)
+/// instead of checking every pair.
+///
+/// Instead of an actual hash map it keeps a list of (cell, particle) sorted by cell, which keeps
+/// the order of the pairs deterministic
+pub struct SpatialHash {
+    cell_size: f32,
+    /// every particle shows up once for each cell its bounding box touches
+    entries: Vec<(Cell, usize)>,
+    /// too big for the grid, checked against everything instead
+    large: Vec<usize>,
+    /// how many particles this was built from
+    particle_count: usize,
+}
+
+impl SpatialHash {
+    pub fn new(particles: &[Particle], cell_size: f32) -> Self {
+        let mut hash = Self {
+            cell_size,
+            entries: Vec::with_capacity(particles.len()),
+            large: Vec::new(),
+            particle_count: particles.len(),
+        };
+
+        for (i, p) in particles.iter().enumerate() {
+            let (min, max) = hash.bounds(p);
+            // the cells saturate at the ends of i32, subtracting them could overflow
+            if max.0.abs_diff(min.0) >= MAX_CELL_SPAN || max.1.abs_diff(min.1) >= MAX_CELL_SPAN {
+                hash.large.push(i);
+                continue;
+            }
+            for x in min.0..=max.0 {
+                for y in min.1..=max.1 {
+                    hash.entries.push(((x, y), i));
+                }
+            }
+        }
+        hash.entries.par_sort_unstable();
+
+        hash
+    }
+
+    /// How many particles this was built from, if that isn't how many there are now it's out of
+    /// date
+    pub fn particle_count(&self) -> usize {
+        self.particle_count
+    }
+
+    /// Mean diameter of the particles, most particles end up in 1 to 4 cells
+    pub fn auto_cell_size(particles: &[Particle]) -> f32 {
+        if particles.is_empty() {
+            return 1.0;
+        }
+        let radii: f32 = particles.iter().map(|p| p.radius).sum();
+        (2.0 * radii / particles.len() as f32).max(0.001)
+    }
+
+    #[inline]
+    fn cell(&self, point: glam::Vec2) -> Cell {
+        let cell = (point / self.cell_size).floor();
+        (cell.x as i32, cell.y as i32)
+    }
+
+    #[inline]
+    fn bounds(&self, particle: &Particle) -> (Cell, Cell) {
+        let radius = glam::Vec2::splat(particle.radius);
+        (
+            self.cell(particle.position - radius),
+            self.cell(particle.position + radius),
+        )
+    }
+
+    /// Every pair `(i, j)` with `i < j` whose bounding boxes overlap, each pair shows up once
+    pub fn pairs(&self, particles: &[Particle]) -> Vec<(usize, usize)> {
+        let mut pairs: Vec<(usize, usize)> = self
+            .entries
+            .par_chunk_by(|a, b| a.0 == b.0)
+            .flat_map_iter(|run| {
+                let cell = run[0].0;
+                let mut pairs = Vec::new();
+                for (n, &(_, i)) in run.iter().enumerate() {
+                    let (min_i, _) = self.bounds(&particles[i]);
+                    for &(_, j) in &run[n + 1..] {
+                        let (min_j, _) = self.bounds(&particles[j]);
+                        // two particles can share several cells, only report them from the one at
+                        // the corner of the overlap so nothing gets resolved twice
+                        if (min_i.0.max(min_j.0), min_i.1.max(min_j.1)) == cell {
+                            pairs.push((i, j));
+                        }
+                    }
+                }
+                pairs
+            })
+            .collect();
+
+        // the large ones against everything, pairs of two large ones only from the first of them
+        for (n, &l) in self.large.iter().enumerate() {
+            let others = (0..particles.len()).filter(|&i| i != l && !self.large[..n].contains(&i));
+            for i in others {
+                if boxes_overlap(&particles[l], &particles[i]) {
+                    pairs.push((l.min(i), l.max(i)));
+                }
+            }
+        }
+
+        pairs
+    }
+
+    /// Particles whose bounding box covers `point`, plus every large one
+    pub fn query(&self, point: glam::Vec2) -> impl Iterator<Item = usize> + '_ {
+        let cell = self.cell(point);
+        let start = self.entries.partition_point(|entry| entry.0 < cell);
+        self.entries[start..]
+            .iter()
+            .take_while(move |entry| entry.0 == cell)
+            .map(|entry| entry.1)
+            .chain(self.large.iter().copied())
+    }
+}
+
+#[inline]
+fn boxes_overlap(a: &Particle, b: &Particle) -> bool {
+    let radii = a.radius + b.radius;
+    let offset = (a.position - b.position).abs();
+    offset.x <= radii && offset.y <= radii
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particle::collision::is_overlapping;
+
+    fn particle(x: f32, y: f32, radius: f32) -> Particle {
+        Particle {
+            position: glam::vec2(x, y),
+            radius,
+            ..Default::default()
+        }
+    }
+
+    /// A row of small particles with a huge one sitting on top of some of them
+    fn particles() -> Vec<Particle> {
+        let mut particles: Vec<_> = (0..50)
+            .map(|i| particle(i as f32 * 3.0, 0.0, 1.0))
+            .collect();
+        particles.insert(10, particle(40.0, 5.0, 30.0));
+        particles.push(particle(100.0, 20.0, 25.0));
+        particles
+    }
+
+    #[test]
+    fn large_particles_skip_the_grid() {
+        let particles = particles();
+        let hash = SpatialHash::new(&particles, 2.0);
+        assert_eq!(hash.large, [10, 51]);
+        assert_eq!(hash.entries.len(), 50 * 4);
+    }
+
+    #[test]
+    fn finds_every_overlapping_pair_once() {
+        let particles = particles();
+        let mut pairs = SpatialHash::new(&particles, 2.0).pairs(&particles);
+        let count = pairs.len();
+        pairs.sort_unstable();
+        pairs.dedup();
+        assert_eq!(pairs.len(), count, "duplicate pairs");
+
+        let overlapping: Vec<_> = pairs
+            .into_iter()
+            .filter(|&(i, j)| is_overlapping(&particles[i], &particles[j]))
+            .collect();
+        let expected: Vec<_> = (0..particles.len())
+            .flat_map(|i| (i + 1..particles.len()).map(move |j| (i, j)))
+            .filter(|&(i, j)| is_overlapping(&particles[i], &particles[j]))
+            .collect();
+        assert_eq!(overlapping, expected);
+    }
+
+    #[test]
+    fn query_includes_large_particles() {
+        let particles = particles();
+        let hash = SpatialHash::new(&particles, 2.0);
+        let point = glam::vec2(40.0, 30.0);
+        assert!(hash.query(point).any(|i| i == 10));
+    }
+
+    #[test]
+    fn enormous_particles_dont_overflow() {
+        let mut particles = particles();
+        particles.push(particle(0.0, 0.0, 1e30));
+        let hash = SpatialHash::new(&particles, 1e-3);
+        assert!(hash.large.contains(&(particles.len() - 1)));
+        assert!(hash.pairs(&particles).contains(&(0, particles.len() - 1)));
+    }
+}
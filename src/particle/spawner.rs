@@ -0,0 +1,118 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+
+use super::scenarios::{gaussian, random_color, random_direction};
+use super::simulation::{Particle, DEFAULT_DENSITY};
+
+/// How the brush scatters particles around the cursor
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Distribution {
+    /// Evenly over a disk, `spread` is its radius
+    Uniform,
+    /// Normal, `spread` is the standard deviation
+    Gaussian,
+}
+
+impl Distribution {
+    pub const ALL: [Distribution; 2] = [Distribution::Uniform, Distribution::Gaussian];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Distribution::Uniform => "Uniform",
+            Distribution::Gaussian => "Gaussian",
+        }
+    }
+}
+
+/// Makes the particles placed with the mouse, a single one launched by dragging or a spray of
+/// them from the brush. All of them use the same size and color settings
+pub struct Spawner {
+    pub radius: f32,
+    pub color: glam::Vec3,
+    /// Ignores `color` and picks a different one for every particle
+    pub random_color: bool,
+    /// Launch speed per world unit the slingshot is pulled back
+    pub launch_strength: f32,
+
+    /// Particles per second while the brush is held down
+    pub brush_rate: f32,
+    pub brush_spread: f32,
+    pub distribution: Distribution,
+    /// Every brushed particle starts with this
+    pub brush_velocity: glam::Vec2,
+
+    rng: StdRng,
+    /// the part of a particle the brush didn't get to spawn last frame
+    brush_carry: f32,
+}
+
+impl Default for Spawner {
+    fn default() -> Self {
+        Self {
+            radius: 1.0,
+            color: glam::Vec3::ONE,
+            random_color: false,
+            launch_strength: 1.0,
+            brush_rate: 60.0,
+            brush_spread: 10.0,
+            distribution: Distribution::Uniform,
+            brush_velocity: glam::Vec2::ZERO,
+            rng: StdRng::from_entropy(),
+            brush_carry: 0.0,
+        }
+    }
+}
+
+impl Spawner {
+    pub fn particle(&mut self, position: glam::Vec2, velocity: glam::Vec2) -> Particle {
+        Particle {
+            position,
+            velocity,
+            color: if self.random_color {
+                random_color(&mut self.rng)
+            } else {
+                self.color
+            },
+            radius: self.radius,
+            mass: Particle::area_mass(self.radius, DEFAULT_DENSITY),
+            pinned: false,
+        }
+    }
+
+    /// Pulled back from `start` to `end` like a slingshot, it flies the other way
+    pub fn launch_velocity(&self, start: glam::Vec2, end: glam::Vec2) -> glam::Vec2 {
+        (start - end) * self.launch_strength
+    }
+
+    /// A particle at `start` flung by dragging to `end`
+    pub fn launch(&mut self, start: glam::Vec2, end: glam::Vec2) -> Particle {
+        let velocity = self.launch_velocity(start, end);
+        self.particle(start, velocity)
+    }
+
+    /// Whatever the brush sprays around `center` in `delta` seconds, at `brush_rate` no matter
+    /// how the frames are spaced out
+    pub fn spray(&mut self, center: glam::Vec2, delta: f32) -> Vec<Particle> {
+        self.brush_carry += self.brush_rate.max(0.0) * delta;
+        let count = self.brush_carry as usize;
+        self.brush_carry -= count as f32;
+
+        (0..count)
+            .map(|_| {
+                let offset = match self.distribution {
+                    Distribution::Uniform => {
+                        random_direction(&mut self.rng)
+                            * self.brush_spread
+                            * self.rng.gen::<f32>().sqrt()
+                    }
+                    Distribution::Gaussian => gaussian(&mut self.rng) * self.brush_spread,
+                };
+                self.particle(center + offset, self.brush_velocity)
+            })
+            .collect()
+    }
+
+    /// Forgets the partial particle left over from the last spray, for when the brush is let go
+    pub fn stop_spraying(&mut self) {
+        self.brush_carry = 0.0;
+    }
+}
@@ -0,0 +1,450 @@
+use anyhow::{bail, Context};
+use std::fs::File;
+use std::io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use super::simulation::{NBodySimulation, Particle};
+
+/// First bytes of every trajectory file
+const MAGIC: [u8; 8] = *b"RPTRAJ\0\0";
+/// Bumped whenever the layout changes
+///
+/// 2: every particle ends with a u32 of flags, bit 0 is `Particle::pinned`
+pub const TRAJECTORY_VERSION: u32 = 2;
+
+/// magic, version, every, timestep, substeps
+const HEADER_SIZE: u64 = 8 + 4 + 4 + 4 + 4;
+/// time, step, particle count
+const FRAME_HEADER_SIZE: u64 = 8 + 8 + 4;
+/// position, velocity, color, radius, mass, flags
+const PARTICLE_SIZE: u64 = 10 * 4;
+/// version 1 had no flags
+const PARTICLE_SIZE_V1: u64 = 9 * 4;
+const PINNED: u32 = 1;
+
+/// What the recording was made with, written once at the start of the file.
+///
+/// The file is the header followed by frames, every frame is its time (f64), step (u64), particle
+/// count (u32) and then that many particles as 9 f32s each: position, velocity, color, radius,
+/// mass, followed by a u32 of flags. Everything is little endian. The particle count can change between frames because of
+/// merges so frames aren't all the same size
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrajectoryHeader {
+    /// A frame gets written every this many steps
+    pub every: u32,
+    /// `FixedTimestep::step`
+    pub timestep: f32,
+    pub substeps: u32,
+}
+
+/// One recorded step
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    /// Simulated seconds
+    pub time: f64,
+    /// `NBodySimulation::steps` when it was recorded
+    pub step: u64,
+    pub particles: Vec<Particle>,
+}
+
+/// Appends every `header.every`th step of a simulation to a trajectory file
+pub struct TrajectoryRecorder<W: Write> {
+    writer: BufWriter<W>,
+    header: TrajectoryHeader,
+    frames: u64,
+    /// step of the first frame, the rest are counted from there
+    first_step: Option<u64>,
+}
+
+impl TrajectoryRecorder<File> {
+    pub fn create(path: impl AsRef<Path>, header: TrajectoryHeader) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file =
+            File::create(path).with_context(|| format!("couldn't create {}", path.display()))?;
+        Self::new(file, header)
+    }
+}
+
+impl<W: Write> TrajectoryRecorder<W> {
+    pub fn new(writer: W, header: TrajectoryHeader) -> anyhow::Result<Self> {
+        if header.every == 0 {
+            bail!("frames have to be recorded at least every step");
+        }
+
+        let mut writer = BufWriter::new(writer);
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&TRAJECTORY_VERSION.to_le_bytes())?;
+        writer.write_all(&header.every.to_le_bytes())?;
+        writer.write_all(&header.timestep.to_le_bytes())?;
+        writer.write_all(&header.substeps.to_le_bytes())?;
+
+        Ok(Self {
+            writer,
+            header,
+            frames: 0,
+            first_step: None,
+        })
+    }
+
+    pub fn header(&self) -> &TrajectoryHeader {
+        &self.header
+    }
+
+    /// Frames written so far
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// Call once when starting and then after every step, writes a frame if it's one of the steps
+    /// being kept. The first call always writes one. Returns whether it did
+    pub fn record(&mut self, simulation: &NBodySimulation) -> anyhow::Result<bool> {
+        let first_step = *self.first_step.get_or_insert(simulation.steps);
+        if !simulation
+            .steps
+            .wrapping_sub(first_step)
+            .is_multiple_of(self.header.every as u64)
+        {
+            return Ok(false);
+        }
+        self.write_frame(simulation.time, simulation.steps, &simulation.particles)?;
+        Ok(true)
+    }
+
+    pub fn write_frame(
+        &mut self,
+        time: f64,
+        step: u64,
+        particles: &[Particle],
+    ) -> anyhow::Result<()> {
+        let count = u32::try_from(particles.len()).context("too many particles for one frame")?;
+
+        self.writer.write_all(&time.to_le_bytes())?;
+        self.writer.write_all(&step.to_le_bytes())?;
+        self.writer.write_all(&count.to_le_bytes())?;
+        for p in particles {
+            for value in [
+                p.position.x,
+                p.position.y,
+                p.velocity.x,
+                p.velocity.y,
+                p.color.x,
+                p.color.y,
+                p.color.z,
+                p.radius,
+                p.mass,
+            ] {
+                self.writer.write_all(&value.to_le_bytes())?;
+            }
+            let flags = if p.pinned { PINNED } else { 0 };
+            self.writer.write_all(&flags.to_le_bytes())?;
+        }
+
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Everything buffered so far ends up in the file, dropping the recorder does this too but
+    /// ignores errors
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(self.writer.flush()?)
+    }
+}
+
+/// Random access to the frames of a trajectory file. Opening it walks over the frame headers
+/// once to find where every frame starts, reading a frame after that is a single seek
+pub struct TrajectoryReader<R: Read + Seek> {
+    reader: R,
+    header: TrajectoryHeader,
+    /// where each frame starts in the file
+    offsets: Vec<u64>,
+    /// time of each frame, for `frame_at`
+    times: Vec<f64>,
+    /// bytes per particle, depends on the version
+    particle_size: u64,
+}
+
+impl TrajectoryReader<BufReader<File>> {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let file = File::open(path).with_context(|| format!("couldn't open {}", path.display()))?;
+        Self::new(BufReader::new(file)).with_context(|| format!("couldn't read {}", path.display()))
+    }
+}
+
+impl<R: Read + Seek> TrajectoryReader<R> {
+    /// A file that got cut off in the middle of a frame (the recording crashed, or is still
+    /// going) just loses that last frame
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        reader.seek(SeekFrom::Start(0))?;
+        let mut magic = [0; 8];
+        reader
+            .read_exact(&mut magic)
+            .context("not a trajectory file")?;
+        if magic != MAGIC {
+            bail!("not a trajectory file");
+        }
+        let version = read_u32(&mut reader)?;
+        if version == 0 || version > TRAJECTORY_VERSION {
+            bail!(
+                "trajectory version {version} isn't supported, expected at most {}",
+                TRAJECTORY_VERSION
+            );
+        }
+        let particle_size = if version == 1 {
+            PARTICLE_SIZE_V1
+        } else {
+            PARTICLE_SIZE
+        };
+        let header = TrajectoryHeader {
+            every: read_u32(&mut reader)?,
+            timestep: read_f32(&mut reader)?,
+            substeps: read_u32(&mut reader)?,
+        };
+
+        let end = reader.seek(SeekFrom::End(0))?;
+        let mut offsets = Vec::new();
+        let mut times = Vec::new();
+        let mut offset = HEADER_SIZE;
+        while offset + FRAME_HEADER_SIZE <= end {
+            reader.seek(SeekFrom::Start(offset))?;
+            let time = read_f64(&mut reader)?;
+            let _step = read_u64(&mut reader)?;
+            let count = read_u32(&mut reader)? as u64;
+
+            let next = offset + FRAME_HEADER_SIZE + count * particle_size;
+            if next > end {
+                break;
+            }
+            offsets.push(offset);
+            times.push(time);
+            offset = next;
+        }
+
+        Ok(Self {
+            reader,
+            header,
+            offsets,
+            times,
+            particle_size,
+        })
+    }
+
+    pub fn header(&self) -> &TrajectoryHeader {
+        &self.header
+    }
+
+    /// Number of complete frames
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+
+    /// Time of every frame, in order
+    pub fn times(&self) -> &[f64] {
+        &self.times
+    }
+
+    /// Reads frame `index`, 0 is the first one
+    pub fn frame(&mut self, index: usize) -> anyhow::Result<Frame> {
+        let Some(&offset) = self.offsets.get(index) else {
+            bail!("frame {index} is past the end, there are {}", self.len());
+        };
+
+        self.reader.seek(SeekFrom::Start(offset))?;
+        let time = read_f64(&mut self.reader)?;
+        let step = read_u64(&mut self.reader)?;
+        let count = read_u32(&mut self.reader)? as usize;
+
+        let mut bytes = vec![0; count * self.particle_size as usize];
+        self.reader.read_exact(&mut bytes)?;
+        let particles = bytes
+            .chunks_exact(self.particle_size as usize)
+            .map(|chunk| {
+                let mut words = chunk.chunks_exact(4).map(|b| [b[0], b[1], b[2], b[3]]);
+                let mut next = || f32::from_le_bytes(words.next().unwrap_or_default());
+                let particle = Particle {
+                    position: glam::Vec2::new(next(), next()),
+                    velocity: glam::Vec2::new(next(), next()),
+                    color: glam::Vec3::new(next(), next(), next()),
+                    radius: next(),
+                    mass: next(),
+                    pinned: false,
+                };
+                // version 1 has no flags, nothing was pinned
+                let flags = words.next().map(u32::from_le_bytes).unwrap_or_default();
+                Particle {
+                    pinned: flags & PINNED != 0,
+                    ..particle
+                }
+            })
+            .collect();
+
+        Ok(Frame {
+            time,
+            step,
+            particles,
+        })
+    }
+
+    /// Index of the last frame at or before `time`, the first frame if `time` is before all of
+    /// them. `None` if there are no frames
+    pub fn frame_at(&self, time: f64) -> Option<usize> {
+        if self.is_empty() {
+            return None;
+        }
+        Some(self.times.partition_point(|&t| t <= time).saturating_sub(1))
+    }
+}
+
+fn read_array<const N: usize>(reader: &mut impl Read) -> anyhow::Result<[u8; N]> {
+    let mut bytes = [0; N];
+    reader.read_exact(&mut bytes).map_err(|e| {
+        if e.kind() == ErrorKind::UnexpectedEof {
+            anyhow::anyhow!("trajectory file ends too early")
+        } else {
+            e.into()
+        }
+    })?;
+    Ok(bytes)
+}
+
+fn read_u32(reader: &mut impl Read) -> anyhow::Result<u32> {
+    Ok(u32::from_le_bytes(read_array(reader)?))
+}
+
+fn read_u64(reader: &mut impl Read) -> anyhow::Result<u64> {
+    Ok(u64::from_le_bytes(read_array(reader)?))
+}
+
+fn read_f32(reader: &mut impl Read) -> anyhow::Result<f32> {
+    Ok(f32::from_le_bytes(read_array(reader)?))
+}
+
+fn read_f64(reader: &mut impl Read) -> anyhow::Result<f64> {
+    Ok(f64::from_le_bytes(read_array(reader)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    const HEADER: TrajectoryHeader = TrajectoryHeader {
+        every: 2,
+        timestep: 1.0 / 120.0,
+        substeps: 3,
+    };
+
+    /// Frame `n` has `n + 1` particles so they're all different sizes, the first one pinned
+    fn frames() -> Vec<Frame> {
+        (0..5)
+            .map(|n| Frame {
+                time: n as f64 * 0.5,
+                step: n * 2,
+                particles: (0..=n)
+                    .map(|i| Particle {
+                        position: glam::vec2(i as f32, n as f32),
+                        velocity: glam::vec2(-(i as f32), 0.5),
+                        color: glam::vec3(0.25, 0.5, 1.0),
+                        radius: 1.0 + i as f32,
+                        mass: 2.0 * n as f32 + 1.0,
+                        pinned: i == 0,
+                    })
+                    .collect(),
+            })
+            .collect()
+    }
+
+    fn recording() -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut recorder = TrajectoryRecorder::new(&mut bytes, HEADER).unwrap();
+        for frame in frames() {
+            recorder
+                .write_frame(frame.time, frame.step, &frame.particles)
+                .unwrap();
+        }
+        recorder.flush().unwrap();
+        drop(recorder);
+        bytes
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut reader = TrajectoryReader::new(Cursor::new(recording())).unwrap();
+        assert_eq!(*reader.header(), HEADER);
+        assert_eq!(reader.len(), 5);
+        for (n, frame) in frames().into_iter().enumerate() {
+            assert_eq!(reader.frame(n).unwrap(), frame);
+        }
+        assert!(reader.frame(5).is_err());
+    }
+
+    #[test]
+    fn seeks_to_any_frame() {
+        let mut reader = TrajectoryReader::new(Cursor::new(recording())).unwrap();
+        let frames = frames();
+        for n in [3, 0, 4, 1] {
+            assert_eq!(reader.frame(n).unwrap(), frames[n]);
+        }
+        assert_eq!(reader.frame_at(1.2), Some(2));
+        assert_eq!(reader.frame_at(-1.0), Some(0));
+        assert_eq!(reader.frame_at(100.0), Some(4));
+    }
+
+    #[test]
+    fn rejects_bad_magic_and_versions() {
+        let mut bytes = recording();
+        bytes[0] = b'X';
+        assert!(TrajectoryReader::new(Cursor::new(bytes)).is_err());
+
+        for version in [0, TRAJECTORY_VERSION + 1] {
+            let mut bytes = recording();
+            bytes[8..12].copy_from_slice(&version.to_le_bytes());
+            assert!(
+                TrajectoryReader::new(Cursor::new(bytes)).is_err(),
+                "version {version}"
+            );
+        }
+    }
+
+    #[test]
+    fn drops_a_truncated_last_frame() {
+        let mut bytes = recording();
+        bytes.truncate(bytes.len() - 7);
+        let mut reader = TrajectoryReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!(reader.len(), 4);
+        assert_eq!(reader.frame(3).unwrap(), frames()[3]);
+
+        // cut off inside the header
+        assert!(TrajectoryReader::new(Cursor::new(recording()[..10].to_vec())).is_err());
+    }
+
+    #[test]
+    fn reads_version_1() {
+        // the same thing without the flags after every particle
+        let bytes = recording();
+        let mut old = bytes[..HEADER_SIZE as usize].to_vec();
+        old[8..12].copy_from_slice(&1u32.to_le_bytes());
+        let mut offset = HEADER_SIZE as usize;
+        for frame in frames() {
+            let end = offset + FRAME_HEADER_SIZE as usize;
+            old.extend_from_slice(&bytes[offset..end]);
+            offset = end;
+            for _ in &frame.particles {
+                old.extend_from_slice(&bytes[offset..offset + PARTICLE_SIZE_V1 as usize]);
+                offset += PARTICLE_SIZE as usize;
+            }
+        }
+
+        let mut reader = TrajectoryReader::new(Cursor::new(old)).unwrap();
+        let frame = reader.frame(4).unwrap();
+        assert!(frame.particles.iter().all(|p| !p.pinned));
+        assert_eq!(
+            frame.particles[2].position,
+            frames()[4].particles[2].position
+        );
+    }
+}
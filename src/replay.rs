@@ -0,0 +1,94 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use crate::particle::simulation::NBodySimulation;
+use crate::particle::trajectory::TrajectoryReader;
+
+/// Plays a recorded trajectory back instead of simulating. The frame being shown lives in
+/// `simulation` so it renders exactly like a live one
+pub struct Replay {
+    reader: TrajectoryReader<BufReader<File>>,
+    /// Only the particles mean anything, it's never stepped
+    pub simulation: NBodySimulation,
+    /// Where the playhead is, in simulated seconds
+    pub time: f64,
+    pub is_playing: bool,
+    pub is_reversed: bool,
+    /// Simulated seconds per real second
+    pub speed: f32,
+    /// index of the frame in `simulation`
+    frame: usize,
+}
+
+impl Replay {
+    pub fn open(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let mut reader = TrajectoryReader::open(path)?;
+        if reader.is_empty() {
+            anyhow::bail!("the trajectory has no frames");
+        }
+        let first = reader.frame(0)?;
+
+        Ok(Self {
+            reader,
+            simulation: NBodySimulation::from_particles(first.particles),
+            time: first.time,
+            is_playing: true,
+            is_reversed: false,
+            speed: 1.0,
+            frame: 0,
+        })
+    }
+
+    /// Time of the first and last frame
+    pub fn duration(&self) -> (f64, f64) {
+        let times = self.reader.times();
+        (times[0], times[times.len() - 1])
+    }
+
+    pub fn frames(&self) -> usize {
+        self.reader.len()
+    }
+
+    /// Index of the frame being shown
+    pub fn frame(&self) -> usize {
+        self.frame
+    }
+
+    /// Moves the playhead along if playing and loads whatever frame it ends up on, also picks up
+    /// changes to `time` made from the ui. Stops at either end
+    pub fn advance(&mut self, delta: f32) -> anyhow::Result<()> {
+        let (start, end) = self.duration();
+
+        if self.is_playing {
+            let direction = if self.is_reversed { -1.0 } else { 1.0 };
+            self.time += (delta * self.speed) as f64 * direction;
+            if (self.is_reversed && self.time <= start) || (!self.is_reversed && self.time >= end) {
+                self.is_playing = false;
+            }
+        }
+        self.time = self.time.clamp(start, end);
+
+        // never `None`, `open` made sure there's at least one frame
+        let frame = self.reader.frame_at(self.time).unwrap_or_default();
+        if frame != self.frame {
+            self.simulation.particles = self.reader.frame(frame)?.particles;
+            self.frame = frame;
+        }
+
+        Ok(())
+    }
+
+    /// Play from the start again if it ran off the end
+    pub fn toggle_playing(&mut self) {
+        self.is_playing = !self.is_playing;
+        if self.is_playing {
+            let (start, end) = self.duration();
+            if !self.is_reversed && self.time >= end {
+                self.time = start;
+            } else if self.is_reversed && self.time <= start {
+                self.time = end;
+            }
+        }
+    }
+}
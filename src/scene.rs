@@ -0,0 +1,235 @@
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+use crate::engine::{camera::Camera2D, timer::FixedTimestep};
+use crate::particle::collision::CollisionMode;
+use crate::particle::force::Forces;
+use crate::particle::integrator;
+use crate::particle::simulation::{GravitySolver, NBodySimulation, Particle};
+
+/// Bumped whenever the layout of `Scene` changes in a way older versions can't read. Files from
+/// a newer version get rejected instead of half loaded, older ones are upgraded in `from_json`
+///
+/// 2: `CameraState::zoom` is the half height of the view instead of its square root
+pub const SCENE_VERSION: u32 = 2;
+
+/// Everything needed to pick a simulation back up where it was saved, stored as json
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Scene {
+    pub version: u32,
+    pub particles: Vec<Particle>,
+    pub simulation: SimulationParameters,
+    pub camera: CameraState,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SimulationParameters {
+    pub forces: Forces,
+    pub solver: GravitySolver,
+    /// `Integrator::name`
+    pub integrator: String,
+    pub collision_mode: CollisionMode,
+    pub cell_size: Option<f32>,
+    pub diagnostics_interval: u32,
+    pub is_running: bool,
+    pub time: f64,
+    pub steps: u64,
+    /// `FixedTimestep::step`
+    pub timestep: f32,
+    pub substeps: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct CameraState {
+    pub position: glam::Vec2,
+    pub zoom: f32,
+    /// Radians counterclockwise, `Camera2D::angle`
+    #[serde(default)]
+    pub angle: f32,
+}
+
+/// Only the version, read first so a file from the future fails with a useful error instead of
+/// whatever field happens to not match
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+impl Scene {
+    pub fn capture(simulation: &NBodySimulation, clock: &FixedTimestep, camera: &Camera2D) -> Self {
+        Self {
+            version: SCENE_VERSION,
+            particles: simulation.particles.clone(),
+            simulation: SimulationParameters {
+                forces: simulation.forces,
+                solver: simulation.solver,
+                integrator: simulation.integrator.name().to_string(),
+                collision_mode: simulation.collision_mode,
+                cell_size: simulation.cell_size,
+                diagnostics_interval: simulation.diagnostics_interval,
+                is_running: simulation.is_running,
+                time: simulation.time,
+                steps: simulation.steps,
+                timestep: clock.step,
+                substeps: clock.substeps,
+            },
+            camera: CameraState {
+                position: camera.position.truncate(),
+                zoom: camera.zoom,
+                angle: camera.angle(),
+            },
+        }
+    }
+
+    /// Puts the clock and the camera back where they were and returns the simulation. The
+    /// diagnostics history starts over
+    pub fn restore(&self, clock: &mut FixedTimestep, camera: &mut Camera2D) -> NBodySimulation {
+        let parameters = &self.simulation;
+
+        clock.step = parameters.timestep;
+        clock.substeps = parameters.substeps;
+        clock.reset();
+
+        camera.position = self.camera.position.extend(camera.position.z);
+        camera.zoom = self.camera.zoom;
+        camera.set_angle(self.camera.angle);
+
+        let mut simulation = NBodySimulation::default();
+        simulation.particles = self.particles.clone();
+        simulation.forces = parameters.forces;
+        simulation.is_running = parameters.is_running;
+        simulation.solver = parameters.solver;
+        // `from_json` already made sure the name is known
+        if let Some(integrator) = integrator::by_name(&parameters.integrator) {
+            simulation.integrator = integrator;
+        }
+        simulation.collision_mode = parameters.collision_mode;
+        simulation.cell_size = parameters.cell_size;
+        simulation.diagnostics_interval = parameters.diagnostics_interval;
+        simulation.time = parameters.time;
+        simulation.steps = parameters.steps;
+        simulation
+    }
+
+    pub fn to_json(&self) -> anyhow::Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        let header: Header = serde_json::from_str(json).context("not a scene file")?;
+        if header.version == 0 || header.version > SCENE_VERSION {
+            bail!(
+                "scene version {} isn't supported, expected at most {SCENE_VERSION}",
+                header.version
+            );
+        }
+
+        let mut scene: Self = serde_json::from_str(json)?;
+        if scene.version < 2 {
+            scene.camera.zoom *= scene.camera.zoom;
+        }
+        scene.version = SCENE_VERSION;
+        if integrator::by_name(&scene.simulation.integrator).is_none() {
+            bail!("unknown integrator \"{}\"", scene.simulation.integrator);
+        }
+        // written this way round so NaN fails too
+        if !(scene.simulation.timestep > 0.0 && scene.simulation.timestep.is_finite())
+            || scene.simulation.substeps == 0
+        {
+            bail!("timestep and substeps have to be positive");
+        }
+        if let Some(index) = scene.particles.iter().position(|p| {
+            !(p.position.is_finite() && p.velocity.is_finite() && p.mass.is_finite())
+                || p.mass <= 0.0
+        }) {
+            bail!("particle {index} has a non finite position, velocity or mass");
+        }
+
+        Ok(scene)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let path = path.as_ref();
+        std::fs::write(path, self.to_json()?)
+            .with_context(|| format!("couldn't write {}", path.display()))
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("couldn't read {}", path.display()))?;
+        Self::from_json(&json).with_context(|| format!("couldn't load {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particle::scenarios::Scenario;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn scene() -> Scene {
+        let mut simulation = Scenario::Binaries.generate(8, &mut StdRng::seed_from_u64(3));
+        simulation.particles[0].pinned = true;
+        simulation.time = 12.5;
+        simulation.steps = 1500;
+        let mut clock = FixedTimestep::new(1.0 / 60.0);
+        clock.substeps = 4;
+        let mut camera = Camera2D::new(16.0 / 9.0);
+        camera.position = glam::vec3(3.0, -2.0, camera.position.z);
+        camera.zoom = 25.0;
+        camera.set_angle(0.75);
+        Scene::capture(&simulation, &clock, &camera)
+    }
+
+    /// `scene` as json with `edit` applied to it
+    fn edited(edit: impl FnOnce(&mut serde_json::Value)) -> String {
+        let mut json = serde_json::to_value(scene()).unwrap();
+        edit(&mut json);
+        json.to_string()
+    }
+
+    #[test]
+    fn round_trips() {
+        let scene = scene();
+        assert_eq!(Scene::from_json(&scene.to_json().unwrap()).unwrap(), scene);
+    }
+
+    #[test]
+    fn migrates_v1_zoom() {
+        let json = edited(|json| {
+            json["version"] = 1.into();
+            json["camera"]["zoom"] = 5.0.into();
+        });
+        let scene = Scene::from_json(&json).unwrap();
+        assert_eq!(scene.version, SCENE_VERSION);
+        assert_eq!(scene.camera.zoom, 25.0);
+    }
+
+    #[test]
+    fn rejects_unsupported_versions() {
+        for version in [0, SCENE_VERSION + 1] {
+            let json = edited(|json| json["version"] = version.into());
+            assert!(Scene::from_json(&json).is_err(), "version {version}");
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_integrators() {
+        let json = edited(|json| json["simulation"]["integrator"] = "Magic".into());
+        assert!(Scene::from_json(&json).is_err());
+    }
+
+    #[test]
+    fn rejects_non_finite_particles() {
+        // json has no NaN or infinity, but anything past f32::MAX overflows into infinity
+        let json = edited(|json| json["particles"][1]["position"][0] = 1e39.into());
+        assert!(Scene::from_json(&json).is_err());
+
+        for mass in [1e39, 0.0, -1.0] {
+            let json = edited(|json| json["particles"][1]["mass"] = mass.into());
+            assert!(Scene::from_json(&json).is_err(), "mass {mass}");
+        }
+    }
+}
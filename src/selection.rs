@@ -0,0 +1,353 @@
+use crate::particle::diagnostics;
+use crate::particle::simulation::{NBodySimulation, Particle};
+
+/// Indices of the selected particles, sorted and without duplicates. Anything that shuffles the
+/// particles around has to clear it
+#[derive(Debug, Clone, Default)]
+pub struct Selection {
+    indices: Vec<usize>,
+}
+
+impl Selection {
+    pub fn indices(&self) -> &[usize] {
+        &self.indices
+    }
+
+    pub fn len(&self) -> usize {
+        self.indices.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    /// The index if exactly one particle is selected
+    pub fn single(&self) -> Option<usize> {
+        match self.indices.as_slice() {
+            &[index] => Some(index),
+            _ => None,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.indices.clear();
+    }
+
+    /// Replaces the selection, or adds to it if `add`
+    pub fn select(&mut self, indices: impl IntoIterator<Item = usize>, add: bool) {
+        if !add {
+            self.indices.clear();
+        }
+        self.indices.extend(indices);
+        self.indices.sort_unstable();
+        self.indices.dedup();
+    }
+
+    pub fn toggle(&mut self, index: usize) {
+        match self.indices.binary_search(&index) {
+            Ok(i) => {
+                self.indices.remove(i);
+            }
+            Err(i) => self.indices.insert(i, index),
+        }
+    }
+
+    pub fn particles<'a>(
+        &'a self,
+        particles: &'a [Particle],
+    ) -> impl Iterator<Item = &'a Particle> {
+        self.indices.iter().filter_map(|&i| particles.get(i))
+    }
+
+    fn for_each_mut(&self, simulation: &mut NBodySimulation, mut f: impl FnMut(&mut Particle)) {
+        for &i in &self.indices {
+            if let Some(p) = simulation.particles.get_mut(i) {
+                f(p);
+            }
+        }
+    }
+
+    /// Removes the selected particles and clears the selection
+    pub fn delete(&mut self, simulation: &mut NBodySimulation) {
+        let mut index = 0;
+        simulation.particles.retain(|_| {
+            let keep = self.indices.binary_search(&index).is_err();
+            index += 1;
+            keep
+        });
+        self.indices.clear();
+        simulation.particles_edited();
+    }
+
+    pub fn recolor(&self, simulation: &mut NBodySimulation, color: glam::Vec3) {
+        self.for_each_mut(simulation, |p| p.color = color);
+    }
+
+    /// Pinned particles keep standing still
+    pub fn set_velocity(&self, simulation: &mut NBodySimulation, velocity: glam::Vec2) {
+        self.for_each_mut(simulation, |p| {
+            if !p.pinned {
+                p.velocity = velocity;
+            }
+        });
+    }
+
+    /// Pinned particles keep standing still
+    pub fn add_velocity(&self, simulation: &mut NBodySimulation, velocity: glam::Vec2) {
+        self.for_each_mut(simulation, |p| {
+            if !p.pinned {
+                p.velocity += velocity;
+            }
+        });
+    }
+
+    /// Pinning also stops them, unpinning leaves them at rest
+    pub fn set_pinned(&self, simulation: &mut NBodySimulation, pinned: bool) {
+        self.for_each_mut(simulation, |p| {
+            p.pinned = pinned;
+            if pinned {
+                p.velocity = glam::Vec2::ZERO;
+            }
+        });
+        // pinned particles don't accelerate
+        simulation.integrator.invalidate();
+    }
+
+    /// Copies the selected particles `offset` away and selects the copies instead
+    pub fn duplicate(&mut self, simulation: &mut NBodySimulation, offset: glam::Vec2) {
+        let first = simulation.particles.len();
+        let copies: Vec<Particle> = self
+            .particles(&simulation.particles)
+            .map(|p| Particle {
+                position: p.position + offset,
+                ..p.clone()
+            })
+            .collect();
+        let count = copies.len();
+        simulation.add_particles(copies);
+        self.select(first..first + count, false);
+    }
+
+    /// Box around the selected particles, see `bounds`
+    pub fn bounds(&self, particles: &[Particle]) -> Option<(glam::Vec2, glam::Vec2)> {
+        bounds(self.particles(particles))
+    }
+
+    /// Mass weighted center of the selected particles, `positions` can be interpolated ones
+    pub fn center_of_mass(
+        &self,
+        particles: &[Particle],
+        positions: impl Fn(usize) -> Option<glam::Vec2>,
+    ) -> Option<glam::Vec2> {
+        let (weighted, mass) = self
+            .indices
+            .iter()
+            .filter_map(|&i| Some((positions(i)?, particles.get(i)?.mass)))
+            .fold(
+                (glam::Vec2::ZERO, 0.0),
+                |(weighted, total), (position, mass)| (weighted + position * mass, total + mass),
+            );
+        (mass > 0.0).then(|| weighted / mass)
+    }
+
+    /// Copies of the selected particles moved so their center of mass is at the origin, ready
+    /// for `paste`
+    pub fn stamp(&self, particles: &[Particle]) -> Vec<Particle> {
+        centered(self.particles(particles).cloned().collect())
+    }
+}
+
+/// Smallest and largest corner of the box around `particles`, radii included. `None` if there
+/// aren't any
+pub fn bounds<'a>(
+    particles: impl IntoIterator<Item = &'a Particle>,
+) -> Option<(glam::Vec2, glam::Vec2)> {
+    particles.into_iter().fold(None, |bounds, p| {
+        let (min, max) = (p.position - p.radius, p.position + p.radius);
+        Some(match bounds {
+            Some((lo, hi)) => (min.min(lo), max.max(hi)),
+            None => (min, max),
+        })
+    })
+}
+
+/// Every particle whose center is inside `polygon`, which is in world space and can be any
+/// shape that doesn't cross itself
+pub fn inside(particles: &[Particle], polygon: &[glam::Vec2]) -> Vec<usize> {
+    if polygon.len() < 3 {
+        return Vec::new();
+    }
+    particles
+        .iter()
+        .enumerate()
+        .filter(|(_, p)| is_inside(p.position, polygon))
+        .map(|(i, _)| i)
+        .collect()
+}
+
+/// Even-odd rule, counts how many edges a ray going right from `point` crosses
+fn is_inside(point: glam::Vec2, polygon: &[glam::Vec2]) -> bool {
+    let mut inside = false;
+    let mut previous = polygon[polygon.len() - 1];
+    for &current in polygon {
+        if (current.y > point.y) != (previous.y > point.y) {
+            let x = current.x
+                + (point.y - current.y) * (previous.x - current.x) / (previous.y - current.y);
+            if point.x < x {
+                inside = !inside;
+            }
+        }
+        previous = current;
+    }
+    inside
+}
+
+/// Moves the particles so their center of mass is at the origin
+pub fn centered(mut particles: Vec<Particle>) -> Vec<Particle> {
+    let center = diagnostics::center_of_mass(&particles);
+    for p in &mut particles {
+        p.position -= center;
+    }
+    particles
+}
+
+/// Adds a copy of `stamp` centered on `at`, returns the indices of the new particles
+pub fn paste(
+    simulation: &mut NBodySimulation,
+    stamp: &[Particle],
+    at: glam::Vec2,
+) -> std::ops::Range<usize> {
+    let first = simulation.particles.len();
+    simulation.add_particles(stamp.iter().map(|p| Particle {
+        position: p.position + at,
+        ..p.clone()
+    }));
+    first..simulation.particles.len()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::particle::collision::CollisionMode;
+
+    /// A row of particles one unit apart, the one at index `i` at `(i, 0)` with mass `i + 1`
+    fn simulation(count: usize) -> NBodySimulation {
+        let mut simulation = NBodySimulation::from_particles(
+            (0..count)
+                .map(|i| Particle {
+                    position: glam::vec2(i as f32, 0.0),
+                    velocity: glam::vec2(0.0, 1.0),
+                    radius: 0.1,
+                    mass: i as f32 + 1.0,
+                    ..Default::default()
+                })
+                .collect(),
+        );
+        simulation.collision_mode = CollisionMode::Ignore;
+        simulation
+    }
+
+    #[test]
+    fn select_keeps_indices_sorted_and_unique() {
+        let mut selection = Selection::default();
+        selection.select([5, 1, 3, 1], false);
+        assert_eq!(selection.indices(), [1, 3, 5]);
+        selection.select([4, 3, 0], true);
+        assert_eq!(selection.indices(), [0, 1, 3, 4, 5]);
+        selection.select([2], false);
+        assert_eq!(selection.indices(), [2]);
+        assert_eq!(selection.single(), Some(2));
+    }
+
+    #[test]
+    fn toggle_keeps_indices_sorted() {
+        let mut selection = Selection::default();
+        for i in [4, 0, 2, 4] {
+            selection.toggle(i);
+        }
+        assert_eq!(selection.indices(), [0, 2]);
+        selection.toggle(0);
+        assert_eq!(selection.single(), Some(2));
+    }
+
+    #[test]
+    fn delete_removes_the_selected_particles() {
+        let mut simulation = simulation(6);
+        let mut selection = Selection::default();
+        selection.select([1, 4], false);
+        selection.delete(&mut simulation);
+
+        assert!(selection.is_empty());
+        let masses: Vec<f32> = simulation.particles.iter().map(|p| p.mass).collect();
+        assert_eq!(masses, [1.0, 3.0, 4.0, 6.0]);
+        // the broadphase has to know about the shifted indices
+        assert_eq!(simulation.particle_at(glam::vec2(1.0, 0.0)), None);
+        assert_eq!(simulation.particle_at(glam::vec2(2.0, 0.0)), Some(1));
+        assert_eq!(simulation.particle_at(glam::vec2(5.0, 0.0)), Some(3));
+    }
+
+    #[test]
+    fn duplicate_selects_the_copies() {
+        let mut simulation = simulation(4);
+        let mut selection = Selection::default();
+        selection.select([0, 2], false);
+        let offset = glam::vec2(0.0, 10.0);
+        selection.duplicate(&mut simulation, offset);
+
+        assert_eq!(simulation.particles.len(), 6);
+        assert_eq!(selection.indices(), [4, 5]);
+        for (copy, original) in [(4, 0), (5, 2)] {
+            let (copy, original) = (&simulation.particles[copy], &simulation.particles[original]);
+            assert_eq!(copy.position, original.position + offset);
+            assert_eq!(copy.mass, original.mass);
+        }
+    }
+
+    #[test]
+    fn inside_uses_the_even_odd_rule() {
+        let particles: Vec<Particle> = [(1.0, 1.0), (3.0, 1.0), (3.0, 3.0), (5.0, 5.0), (0.5, 3.5)]
+            .into_iter()
+            .map(|(x, y)| Particle {
+                position: glam::vec2(x, y),
+                ..Default::default()
+            })
+            .collect();
+        // a U shape, the notch between its arms is outside
+        let polygon = [
+            glam::vec2(0.0, 0.0),
+            glam::vec2(4.0, 0.0),
+            glam::vec2(4.0, 4.0),
+            glam::vec2(3.5, 4.0),
+            glam::vec2(3.5, 2.0),
+            glam::vec2(1.5, 2.0),
+            glam::vec2(1.5, 4.0),
+            glam::vec2(0.0, 4.0),
+        ];
+        assert_eq!(inside(&particles, &polygon), [0, 1, 4]);
+        assert!(inside(&particles, &polygon[..2]).is_empty());
+    }
+
+    #[test]
+    fn pinning_stops_particles_where_they_are() {
+        let mut simulation = simulation(3);
+        // gets the integrator to remember accelerations from before the pin
+        for _ in 0..3 {
+            simulation.step(1.0 / 120.0, 1);
+        }
+
+        let mut selection = Selection::default();
+        selection.select([1], false);
+        selection.set_pinned(&mut simulation, true);
+        assert_eq!(simulation.particles[1].velocity, glam::Vec2::ZERO);
+        let position = simulation.particles[1].position;
+        for _ in 0..3 {
+            simulation.step(1.0 / 120.0, 1);
+        }
+        assert_eq!(simulation.particles[1].position, position);
+
+        // unpinned they stay at rest until something pulls on them
+        selection.set_pinned(&mut simulation, false);
+        assert!(!simulation.particles[1].pinned);
+        assert_eq!(simulation.particles[1].velocity, glam::Vec2::ZERO);
+    }
+}